@@ -1,8 +1,19 @@
+use clap::ArgMatches;
 use structopt::StructOpt;
 
-use crate::store::{NETWORK_TOPOLOGY_STRATEGY, SIMPLE_STRATEGY};
+use crate::store::{
+    CONSISTENCY_LEVELS, DEFAULT_HISTORY_TABLE, NETWORK_TOPOLOGY_STRATEGY, RANDOM, ROUND_ROBIN,
+    ROUND_ROBIN_SYNC, SIMPLE_STRATEGY, SINGLE_NODE,
+};
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+// Names of secret files, which match names of the database arguments
+const SECRET_NODE: &str = "node";
+const SECRET_KEYSPACE: &str = "keyspace";
+const SECRET_USER: &str = "user";
+const SECRET_PASSWORD: &str = "password";
 
 #[derive(Debug, StructOpt)]
 pub struct Init {
@@ -13,6 +24,22 @@ pub struct Init {
     /// Replication factor
     #[structopt(long = "replication-factor", default_value = "1")]
     pub replication_factor: usize,
+
+    /// Options of the migrations table, e.g. "WITH default_time_to_live = 0"
+    #[structopt(long = "migrations-table-options")]
+    pub migrations_table_options: Option<String>,
+
+    /// Prints the files which would be created, without creating them
+    #[structopt(long = "dry-run")]
+    pub dry_run: bool,
+
+    /// Regenerates files of the existing initial migration, keeping its version
+    #[structopt(long)]
+    pub update: bool,
+
+    /// Confirms `--update` of the initial migration, which may be already applied
+    #[structopt(long, requires = "update")]
+    pub force: bool,
 }
 
 #[derive(Debug, StructOpt)]
@@ -20,6 +47,141 @@ pub struct New {
     /// Name of a new migration
     #[structopt(short, long)]
     pub name: String,
+
+    /// Creates the `up` file only, the migration can't be rolled back
+    #[structopt(long)]
+    pub irreversible: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Diff {
+    /// Schema snapshot to migrate from
+    #[structopt(long = "from")]
+    pub from_file: PathBuf,
+
+    /// Schema snapshot to migrate to
+    #[structopt(long = "to")]
+    pub to_file: PathBuf,
+
+    /// Name of a new migration
+    #[structopt(short, long, default_value = "schema_diff")]
+    pub name: String,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Validate {
+    /// Fails if timestamps of migrations don't increase in name order
+    #[structopt(long)]
+    pub monotonic: bool,
+
+    /// Reports all broken migrations instead of stopping at the first one
+    #[structopt(long)]
+    pub continue_on_parse_error: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Reset {
+    /// Prints statements which would be executed, highlighting destructive ones
+    #[structopt(long, alias = "dry-run")]
+    pub show: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Migrate {
+    /// Runs only pending migrations with this tag in `-- +migrate Tags` directive
+    #[structopt(long)]
+    pub tag: Option<String>,
+
+    /// Runs only pending migrations whose last run failed
+    #[structopt(long)]
+    pub only_failed: bool,
+
+    /// Runs migrations even if their timestamps are used more than once
+    #[structopt(long)]
+    pub allow_duplicates: bool,
+
+    /// Runs migrations even if files of applied ones were changed since
+    #[structopt(long)]
+    pub ignore_checksums: bool,
+
+    /// Prints statements which would be executed, highlighting destructive ones
+    #[structopt(long)]
+    pub dry_run: bool,
+
+    /// Fails if the keyspace has no history instead of applying the initial migration
+    #[structopt(long)]
+    pub require_initialized: bool,
+
+    /// Skips pending migrations newer than the given one, as if migrating to it
+    #[structopt(long, env = "VEMIGRATE_MAX_VERSION")]
+    pub max_version: Option<u64>,
+
+    /// Ids of tenants separated by commas, runs migrations in the keyspace
+    /// `<keyspace>_<tenant>` of every tenant with its own history. Ids may only
+    /// contain letters, digits and underscores
+    #[structopt(long, env = "VEMIGRATE_TENANTS", use_delimiter = true)]
+    pub tenants: Vec<String>,
+
+    /// File with an id of a tenant per line, added to `--tenants`
+    #[structopt(long)]
+    pub tenants_file: Option<PathBuf>,
+}
+
+impl Migrate {
+    /// Returns tenants of both `--tenants` and `--tenants-file`,
+    /// empty lines and lines starting with `#` are skipped.
+    pub fn all_tenants(&self) -> io::Result<Vec<String>> {
+        let mut tenants = self.tenants.clone();
+        if let Some(ref path) = self.tenants_file {
+            let content = fs::read_to_string(path)?;
+            tenants.extend(
+                content
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(String::from),
+            );
+        }
+        Ok(tenants)
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Shadow {
+    /// Also rolls back every migration right after applying it
+    #[structopt(long)]
+    pub reversible: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Describe {
+    /// Prints a JSON document with statements and checksums, meant for other tools
+    #[structopt(long = "json-schema")]
+    pub json_schema: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct DumpSchema {
+    /// File to write the schema to, stdout by default
+    #[structopt(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Goto {
+    /// Id of the migration to migrate up or down to
+    pub version: u64,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Script {
+    /// Rolls back the given count of the latest migrations instead of applying pending ones
+    #[structopt(long)]
+    pub down: Option<usize>,
+
+    /// File to write the script to, stdout by default
+    #[structopt(short, long)]
+    pub output: Option<PathBuf>,
 }
 
 #[derive(Debug, StructOpt)]
@@ -29,6 +191,17 @@ pub struct MigrationsCount {
     pub count: usize,
 }
 
+#[derive(Debug, StructOpt)]
+pub struct Undo {
+    /// Count of migrations
+    #[structopt(short, long, default_value = "1")]
+    pub count: usize,
+
+    /// Prints statements which would be executed, highlighting destructive ones
+    #[structopt(long)]
+    pub dry_run: bool,
+}
+
 #[derive(Debug, StructOpt)]
 pub enum Command {
     /// Creates the migrations dir and the initial migration.
@@ -41,11 +214,15 @@ pub enum Command {
 
     /// Runs all pending migrations.
     #[structopt(name = "migrate")]
-    Migrate,
+    Migrate(Migrate),
+
+    /// Runs all migrations in a temporary copy of the keyspace, then drops it.
+    #[structopt(name = "shadow")]
+    Shadow(Shadow),
 
     /// Rolls back all migrations
     #[structopt(name = "reset")]
-    Reset,
+    Reset(Reset),
 
     /// Runs `n` pending migrations.
     #[structopt(name = "do")]
@@ -53,18 +230,88 @@ pub enum Command {
 
     /// Undoes `n` the latest migrations.
     #[structopt(name = "undo")]
-    Undo(MigrationsCount),
+    Undo(Undo),
 
     /// Re-runs last migration.
     #[structopt(name = "redo")]
     Redo,
+
+    /// Migrates up or down, so that the given migration is the latest applied one.
+    #[structopt(name = "goto")]
+    Goto(Goto),
+
+    /// Checks that `up` and `down` files of all migrations can be parsed.
+    #[structopt(name = "validate")]
+    Validate(Validate),
+
+    /// Creates new migration from the difference of tables in two schema snapshots.
+    #[structopt(name = "diff")]
+    Diff(Diff),
+
+    /// Lists all migrations with their status.
+    #[structopt(name = "describe")]
+    Describe(Describe),
+
+    /// Prints the status of every migration, including applied ones without files.
+    #[structopt(name = "status")]
+    Status,
+
+    /// Prints the id of the latest applied migration.
+    #[structopt(name = "version")]
+    Version,
+
+    /// Prints tables of the keyspace as they currently are in the database.
+    #[structopt(name = "dump-schema")]
+    DumpSchema(DumpSchema),
+
+    /// Prints statements of pending migrations as a CQL script to run with `cqlsh`.
+    #[structopt(name = "script")]
+    Script(Script),
 }
 
 #[derive(Debug, StructOpt)]
 pub struct Database {
-    /// Database node address.
+    /// Database node address, or addresses of many nodes separated by commas.
     #[structopt(long = "db-node", env = "VEMIGRATE_NODE_ADDR")]
-    pub node: String,
+    pub node: Option<String>,
+
+    /// DNS name resolving to addresses of all database nodes, used instead of `--db-node`.
+    #[structopt(long = "db-discover-dns", env = "VEMIGRATE_DISCOVER_DNS")]
+    pub discover_dns: Option<String>,
+
+    /// Connects to every node before running the command, reporting nodes which are down.
+    #[structopt(long = "precheck-nodes")]
+    pub precheck_nodes: bool,
+
+    /// Strategy choosing the node for every query.
+    #[structopt(
+        long = "db-load-balancing",
+        env = "VEMIGRATE_LOAD_BALANCING",
+        default_value = "round-robin",
+        possible_values = &[ROUND_ROBIN, ROUND_ROBIN_SYNC, RANDOM, SINGLE_NODE]
+    )]
+    pub load_balancing: String,
+
+    /// Consistency of reads and writes of the migrations history
+    #[structopt(
+        long = "db-history-consistency",
+        env = "VEMIGRATE_HISTORY_CONSISTENCY",
+        default_value = "quorum",
+        possible_values = CONSISTENCY_LEVELS
+    )]
+    pub history_consistency: String,
+
+    /// Checks the keyspace exists with `USE` and reads columns of the history tables
+    /// with a select instead of reading `system_schema`, for roles without access to it.
+    /// The schema dump still reads `system_schema`.
+    #[structopt(long = "db-use-keyspace-check")]
+    pub use_keyspace_check: bool,
+
+    /// CA certificate file to verify nodes with, enables TLS. Requires the `ssl`
+    /// feature. Without it a node requiring TLS drops the connection, which
+    /// fails with an IO error once the connection timeout passes.
+    #[structopt(long = "db-ssl-ca", env = "VEMIGRATE_SSL_CA")]
+    pub ssl_ca: Option<PathBuf>,
 
     /// Database keyspace.
     #[structopt(
@@ -74,13 +321,82 @@ pub struct Database {
     )]
     pub keyspace: String,
 
+    /// Table of the keyspace keeping the migrations history.
+    #[structopt(
+        long = "db-table",
+        env = "VEMIGRATE_TABLE",
+        default_value = DEFAULT_HISTORY_TABLE
+    )]
+    pub table: String,
+
     /// Database user.
     #[structopt(long = "db-user", env = "VEMIGRATE_USER")]
-    pub user: String,
+    pub user: Option<String>,
 
-    /// Database password.
+    /// Database password, asked for on the terminal when not given.
     #[structopt(long = "db-password", env = "VEMIGRATE_PASSWORD")]
-    pub password: String,
+    pub password: Option<String>,
+
+    /// Directory with `node`, `keyspace`, `user` and `password` files,
+    /// they take precedence over environment variables but not over flags.
+    #[structopt(long = "secrets-dir", env = "VEMIGRATE_SECRETS_DIR")]
+    pub secrets_dir: Option<PathBuf>,
+}
+
+impl Database {
+    /// Replaces values which were not passed as flags with files from the secrets dir.
+    fn read_secrets(&mut self, dir: &Path, matches: &ArgMatches) -> io::Result<()> {
+        read_secret_into(dir, SECRET_NODE, matches, &mut self.node)?;
+        read_secret_into(dir, SECRET_USER, matches, &mut self.user)?;
+        read_secret_into(dir, SECRET_PASSWORD, matches, &mut self.password)?;
+
+        let mut keyspace = None;
+        read_secret_into(dir, SECRET_KEYSPACE, matches, &mut keyspace)?;
+        if let Some(keyspace) = keyspace {
+            self.keyspace = keyspace;
+        }
+        Ok(())
+    }
+}
+
+/// Reads a secret file into the field, unless the value was passed as a flag.
+fn read_secret_into(
+    dir: &Path,
+    name: &str,
+    matches: &ArgMatches,
+    field: &mut Option<String>,
+) -> io::Result<()> {
+    if matches.occurrences_of(name) > 0 {
+        return Ok(());
+    }
+    if let Some(val) = read_secret(dir, name)? {
+        *field = Some(val);
+    }
+    Ok(())
+}
+
+/// Reads a secret file, returns None if it doesn't exist.
+fn read_secret(dir: &Path, name: &str) -> io::Result<Option<String>> {
+    match fs::read_to_string(dir.join(name)) {
+        Ok(val) => Ok(Some(val.trim().to_string())),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub struct MigratorOptions {
+    /// Name recorded in the history as the one who ran migrations
+    #[structopt(long = "by", env = "USER")]
+    pub applied_by: Option<String>,
+
+    /// Limits CQL sent by migrations to about this many bytes per second
+    #[structopt(long = "rate-bytes", env = "VEMIGRATE_RATE_BYTES")]
+    pub rate_bytes: Option<u64>,
+
+    /// Records content of applied migrations in the history, which makes it grow
+    #[structopt(long)]
+    pub store_content: bool,
 }
 
 /// Database migrations tool for Scylla.
@@ -96,6 +412,13 @@ pub struct Configs {
     #[structopt(short, long, default_value = "./migrations")]
     pub path: PathBuf,
 
+    #[structopt(flatten)]
+    pub migrator: MigratorOptions,
+
+    /// Doesn't print the summary after migrating
+    #[structopt(short, long)]
+    pub quiet: bool,
+
     // The number of occurrences of the `v/verbose` flag
     /// Verbose mode (-v, -vv, -vvv, etc.)
     #[structopt(short, parse(from_occurrences))]
@@ -104,6 +427,17 @@ pub struct Configs {
 
 impl Configs {
     pub fn parse() -> Self {
-        Self::from_args()
+        let matches = Self::clap().get_matches();
+        let mut cfg = Self::from_clap(&matches);
+        if let Some(dir) = cfg.db.secrets_dir.clone() {
+            if let Err(err) = cfg.db.read_secrets(&dir, &matches) {
+                clap::Error::with_description(
+                    &format!("failed to read secrets from {}: {}", dir.display(), err),
+                    clap::ErrorKind::Io,
+                )
+                .exit();
+            }
+        }
+        cfg
     }
 }
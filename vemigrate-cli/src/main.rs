@@ -8,19 +8,25 @@ extern crate cdrs_helpers_derive;
 extern crate log;
 
 use log::{LevelFilter, Metadata, Record};
-use vemigrate::Migrator;
+use vemigrate::{
+    MigrationOutcome, Migrator, INITIAL_MIGRATION_NAME, MIGRATION_FILE_DOWN, MIGRATION_FILE_UP,
+};
 
 mod configs;
 mod store;
 
-use configs::{Command, Configs};
-use store::{ReplicationStrategy, ScyllaStore};
+use configs::{Command, Configs, Database, Migrate, MigratorOptions};
+use store::{
+    check_nodes, discover_nodes, parse_consistency, LoadBalancing, ReplicationStrategy, ScyllaStore,
+};
 
 use std::fmt::Display;
 use std::fs;
-use std::path::PathBuf;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-const INITIAL_MIGRATION_NAME: &str = "initial";
+const MAX_KEYSPACE_NAME_LENGTH: usize = 48;
 const NEW_FILE_CONTENT: &str = "-- Add your migration query below";
 
 struct SimpleLogger;
@@ -58,17 +64,61 @@ fn main() {
     match cfg.cmd {
         // Create migrations directory, and initial migration.
         Command::Init(args) => {
-            if cfg.path.exists() {
+            if args.update {
+                if !cfg.path.exists() {
+                    return fatal_err("please do `cargo-cli init` first");
+                }
+            } else if cfg.path.exists() {
                 return fatal_err("migrations dir already exists");
             }
 
             let replication_strategy =
                 ReplicationStrategy::from_str(&args.replication_strategy).unwrap();
+            let table_options = args.migrations_table_options.as_deref();
+            if let Some(options) = table_options {
+                ScyllaStore::check_table_options(options).unwrap_or_else(fatal_err);
+            }
+            if args.update {
+                if !args.force {
+                    return fatal_err(
+                        "the initial migration may be already applied, \
+                         its new DDL will differ from the database, use --force to update it",
+                    );
+                }
+                let migration_path = reinitiate(
+                    &cfg.path,
+                    &cfg.db.keyspace,
+                    &cfg.db.table,
+                    replication_strategy,
+                    args.replication_factor,
+                    table_options,
+                )
+                .unwrap_or_else(fatal_err)
+                .unwrap_or_else(|| fatal_err("initial migration not found"));
+                warn!(
+                    "{} was updated, an applied initial migration is not changed in the database",
+                    migration_path.display()
+                );
+                return;
+            }
+            if args.dry_run {
+                return print_initiate_plan(
+                    &cfg.path,
+                    &cfg.db.keyspace,
+                    &cfg.db.table,
+                    replication_strategy,
+                    args.replication_factor,
+                    table_options,
+                );
+            }
+
             let migration_path = initiate(
                 &cfg.path,
                 &cfg.db.keyspace,
+                &cfg.db.table,
                 replication_strategy,
                 args.replication_factor,
+                table_options,
             )
             .unwrap_or_else(fatal_err);
             info!("{} was created", migration_path.display())
@@ -79,65 +129,250 @@ fn main() {
                 return fatal_err("please do `cargo-cli init` first");
             }
 
-            let migration_path = vemigrate::create_migration(
-                &args.name,
-                cfg.path,
-                NEW_FILE_CONTENT,
-                NEW_FILE_CONTENT,
-            )
+            let same_name: Vec<String> = vemigrate::discover(&cfg.path)
+                .unwrap_or_else(fatal_err)
+                .into_iter()
+                .filter(|m| m.short_name() == args.name)
+                .map(|m| m.name)
+                .collect();
+            if !same_name.is_empty() {
+                warn!(
+                    "migration named '{}' already exists in {}, consider a more specific name",
+                    args.name,
+                    same_name.join(", ")
+                );
+            }
+
+            let migration_path = if args.irreversible {
+                vemigrate::create_irreversible_migration(&args.name, cfg.path, NEW_FILE_CONTENT)
+            } else {
+                vemigrate::create_migration(
+                    &args.name,
+                    cfg.path,
+                    NEW_FILE_CONTENT,
+                    NEW_FILE_CONTENT,
+                )
+            }
             .unwrap_or_else(fatal_err);
             info!("{} was created", migration_path.display())
         }
+        // Parse all migration files without touching the database
+        Command::Validate(args) => {
+            if !cfg.path.exists() {
+                return fatal_err("please do `cargo-cli init` first");
+            }
+
+            if args.continue_on_parse_error {
+                let broken = vemigrate::validate_all(&cfg.path).unwrap_or_else(fatal_err);
+                for (migration, err) in &broken {
+                    error!("{}: {}", migration.name, err);
+                }
+                if !broken.is_empty() {
+                    return fatal_err(format!("{} migration(s) are broken", broken.len()));
+                }
+            } else {
+                vemigrate::validate(&cfg.path).unwrap_or_else(fatal_err);
+            }
+            if args.monotonic {
+                let ids = vemigrate::check_monotonic(&cfg.path).unwrap_or_else(fatal_err);
+                if !ids.is_empty() {
+                    return fatal_err(format!("migrations are out of order: {:?}", ids));
+                }
+            }
+            info!("all migrations are valid")
+        }
+        // Scaffold new migration from two schema snapshots
+        Command::Diff(args) => {
+            if !cfg.path.exists() {
+                return fatal_err("please do `cargo-cli init` first");
+            }
+
+            let from = fs::read_to_string(&args.from_file).unwrap_or_else(fatal_err);
+            let to = fs::read_to_string(&args.to_file).unwrap_or_else(fatal_err);
+            let diff = vemigrate::diff_schemas(&from, &to).unwrap_or_else(fatal_err);
+            if diff.is_empty() {
+                return info!("no difference found in tables");
+            }
+
+            let migration_path =
+                vemigrate::create_migration(&args.name, cfg.path, diff.up_cql(), diff.down_cql())
+                    .unwrap_or_else(fatal_err);
+            info!(
+                "{} was created, please review it before applying",
+                migration_path.display()
+            )
+        }
         // Check another subcommands that require db instance
         cmd => {
             if !cfg.path.exists() {
                 return fatal_err("please do `cargo-cli init` first");
             }
 
+            if let Command::Migrate(ref args) = cmd {
+                let tenants = args.all_tenants().unwrap_or_else(fatal_err);
+                if !tenants.is_empty() {
+                    return migrate_tenants(
+                        &cfg.db,
+                        &cfg.path,
+                        &cfg.migrator,
+                        args,
+                        &tenants,
+                        cfg.quiet,
+                    );
+                }
+            }
+
             // Create Migrator instance with Scylla as a store for migrations
-            let db = ScyllaStore::with_session(
-                &cfg.db.node,
-                &cfg.db.keyspace,
-                &cfg.db.user,
-                &cfg.db.password,
-            )
-            .unwrap_or_else(fatal_err);
-            let migrator = Migrator::with_store(&cfg.path, db);
+            let shadow_keyspace = match cmd {
+                Command::Shadow(_) => Some(shadow_keyspace_name(&cfg.db.keyspace)),
+                _ => None,
+            };
+            let keyspace = shadow_keyspace.as_deref().unwrap_or(&cfg.db.keyspace);
+            let db = connect(&cfg.db, keyspace);
+            let db = if shadow_keyspace.is_some() {
+                db.rewrite_keyspace(&cfg.db.keyspace)
+            } else {
+                db
+            };
+            if let Command::DumpSchema(args) = cmd {
+                let schema = db.dump_schema().unwrap_or_else(fatal_err);
+                return match args.output {
+                    Some(path) => {
+                        fs::write(&path, schema).unwrap_or_else(fatal_err);
+                        info!("{} was created", path.display())
+                    }
+                    None => println!("{}", schema),
+                };
+            }
+            let migrator = new_migrator(&cfg.path, db, &cfg.migrator);
 
             // Do stuff depends on subcommand
             match cmd {
-                Command::Migrate => {
-                    info!("execute pending migrations");
-                    match migrator.migrate_up() {
-                        Ok(Some(id)) => info!("migrated up to {}", id),
+                Command::Migrate(args) if args.dry_run => {
+                    let migrator = with_migrate_args(migrator, &args);
+                    print_plan(&migrator.plan(true, None).unwrap_or_else(fatal_err));
+                }
+                Command::Migrate(args) => {
+                    match args.tag {
+                        Some(ref tag) => info!("execute pending migrations tagged {}", tag),
+                        None => info!("execute pending migrations"),
+                    }
+                    let migrator = with_migrate_args(migrator, &args);
+                    match migrator.migrate_outcome(true, None) {
+                        Ok(Some(outcome)) => print_outcome(&outcome, cfg.quiet),
                         Ok(None) => info!("no pending migrations found"),
                         Err(err) => fatal_err(err),
                     };
                 }
-                Command::Reset => {
+                Command::Describe(args) => {
+                    let description = migrator.describe().unwrap_or_else(fatal_err);
+                    if args.json_schema {
+                        println!("{}", description.to_json().unwrap_or_else(fatal_err));
+                    } else {
+                        for m in &description.migrations {
+                            println!("{:?}\t{}", m.status, m.name);
+                        }
+                    }
+                }
+                Command::Status => {
+                    for state in migrator.status().unwrap_or_else(fatal_err) {
+                        let name = state.name.as_deref().unwrap_or("-");
+                        println!("{:?}\t{}\t{}", state.status, state.id, name);
+                    }
+                }
+                Command::Script(args) => {
+                    let (up, n) = match args.down {
+                        Some(count) => (false, Some(count)),
+                        None => (true, None),
+                    };
+                    let plan = migrator.plan(up, n).unwrap_or_else(fatal_err);
+                    if plan.is_empty() {
+                        return info!("no migrations found");
+                    }
+                    let script = plan_script(&plan, up, |q| migrator.store().rewrite_query(q));
+                    match args.output {
+                        Some(path) => {
+                            fs::write(&path, script).unwrap_or_else(fatal_err);
+                            info!("{} was created", path.display())
+                        }
+                        None => print!("{}", script),
+                    };
+                }
+                Command::Version => {
+                    if !migrator.store().keyspace_exists().unwrap_or_else(fatal_err) {
+                        return fatal_err(format!("keyspace {} does not exist", keyspace));
+                    }
+                    match migrator.current_version().unwrap_or_else(fatal_err) {
+                        Some(id) => println!("{}", id),
+                        None => println!("none"),
+                    }
+                }
+                Command::Shadow(args) => {
+                    info!("execute all migrations in the keyspace {}", keyspace);
+                    let res = if args.reversible {
+                        migrator.verify_reversible().map(|()| None)
+                    } else {
+                        migrator.migrate_up()
+                    };
+                    // Drop the copy whatever the result is
+                    if let Err(err) = migrator.store().drop_keyspace() {
+                        error!("failed to drop keyspace {}: {}", keyspace, err);
+                    }
+                    match res {
+                        Ok(Some(id)) => info!("all migrations were applied up to {}", id),
+                        Ok(None) if args.reversible => info!("all migrations are reversible"),
+                        Ok(None) => info!("no migrations found"),
+                        Err(err) => fatal_err(err),
+                    };
+                }
+                Command::Reset(args) if args.show => {
+                    let plan = migrator.plan_down(None).unwrap_or_else(fatal_err);
+                    print_plan(&plan);
+                }
+                Command::Reset(_) => {
                     info!("rollback all migrations");
-                    match migrator.migrate_down() {
-                        Ok(Some(id)) => info!("migrated down to {}", id),
+                    match migrator.migrate_outcome(false, None) {
+                        Ok(Some(outcome)) => print_outcome(&outcome, cfg.quiet),
                         Ok(None) => info!("no migrations found"),
                         Err(err) => fatal_err(err),
                     };
                 }
                 Command::Do(n) => {
                     info!("execute {} migrations", n.count);
-                    match migrator.migrate_up_n(n.count) {
-                        Ok(Some(id)) => info!("migrated up to {}", id),
+                    match migrator.migrate_outcome(true, Some(n.count)) {
+                        Ok(Some(outcome)) => print_outcome(&outcome, cfg.quiet),
                         Ok(None) => info!("no pending migrations found"),
                         Err(err) => fatal_err(err),
                     };
                 }
+                Command::Undo(n) if n.dry_run => {
+                    let plan = migrator.plan_down(Some(n.count)).unwrap_or_else(fatal_err);
+                    print_plan(&plan);
+                }
                 Command::Undo(n) => {
                     info!("rollback {} migrations", n.count);
-                    match migrator.migrate_down_n(n.count) {
-                        Ok(Some(id)) => info!("migrated down to {}", id),
+                    match migrator.migrate_outcome(false, Some(n.count)) {
+                        Ok(Some(outcome)) => print_outcome(&outcome, cfg.quiet),
                         Ok(None) => info!("no migrations found"),
                         Err(err) => fatal_err(err),
                     };
                 }
+                Command::Goto(args) => {
+                    let before = migrator.current_version().unwrap_or_else(fatal_err);
+                    let outcomes = migrator
+                        .migrate_to_outcome(args.version)
+                        .unwrap_or_else(fatal_err);
+                    for outcome in &outcomes {
+                        print_outcome(outcome, cfg.quiet);
+                    }
+                    match before {
+                        _ if outcomes.is_empty() => info!("already at version {}", args.version),
+                        Some(before) if args.version < before => {
+                            info!("rolled back to version {}", args.version)
+                        }
+                        _ => info!("migrated up to version {}", args.version),
+                    }
+                }
                 Command::Redo => {
                     info!("redo the last migration");
                     match migrator.migrate_down_n(1) {
@@ -162,8 +397,10 @@ fn main() {
 fn initiate(
     path: &PathBuf,
     keyspace: &str,
+    table: &str,
     replication_strategy: ReplicationStrategy,
     replication_factor: usize,
+    table_options: Option<&str>,
 ) -> std::io::Result<PathBuf> {
     if !path.exists() {
         create_migrations_dir(path)?;
@@ -172,14 +409,379 @@ fn initiate(
     vemigrate::create_migration(
         INITIAL_MIGRATION_NAME,
         path,
-        ScyllaStore::initial_migration_up(keyspace, replication_strategy, replication_factor),
-        ScyllaStore::initial_migration_down(keyspace),
+        ScyllaStore::initial_migration_up(
+            keyspace,
+            table,
+            replication_strategy,
+            replication_factor,
+            table_options,
+        ),
+        ScyllaStore::initial_migration_down(keyspace, table),
     )
 }
 
+/// Overwrites files of the initial migration keeping its directory,
+/// returns None if there is no initial migration.
+fn reinitiate(
+    path: &PathBuf,
+    keyspace: &str,
+    table: &str,
+    replication_strategy: ReplicationStrategy,
+    replication_factor: usize,
+    table_options: Option<&str>,
+) -> vemigrate::Result<Option<PathBuf>> {
+    let initial = match vemigrate::discover(path)?.into_iter().find(|m| m.initial) {
+        Some(initial) => initial,
+        None => return Ok(None),
+    };
+    fs::write(
+        initial.file(true),
+        ScyllaStore::initial_migration_up(
+            keyspace,
+            table,
+            replication_strategy,
+            replication_factor,
+            table_options,
+        ),
+    )?;
+    fs::write(
+        initial.file(false),
+        ScyllaStore::initial_migration_down(keyspace, table),
+    )?;
+    Ok(Some(initial.path))
+}
+
+/// Prints what `initiate` would create with the same arguments.
+fn print_initiate_plan(
+    path: &PathBuf,
+    keyspace: &str,
+    table: &str,
+    replication_strategy: ReplicationStrategy,
+    replication_factor: usize,
+    table_options: Option<&str>,
+) {
+    if !path.exists() {
+        println!("would create migrations directory at: {}", path.display());
+    }
+
+    let migration_path = vemigrate::migration_path(INITIAL_MIGRATION_NAME, path);
+    println!(
+        "would create {}:\n{}\n",
+        migration_path.join(MIGRATION_FILE_UP).display(),
+        ScyllaStore::initial_migration_up(
+            keyspace,
+            table,
+            replication_strategy,
+            replication_factor,
+            table_options
+        )
+    );
+    println!(
+        "would create {}:\n{}",
+        migration_path.join(MIGRATION_FILE_DOWN).display(),
+        ScyllaStore::initial_migration_down(keyspace, table)
+    );
+}
+
+/// Prints the summary of the run unless `quiet` is set.
+fn print_outcome(outcome: &MigrationOutcome, quiet: bool) {
+    if quiet {
+        return;
+    }
+    println!("{}", outcome);
+    println!("migrations: {:?}", outcome.applied);
+    if let Some(timings) = outcome.timings().filter(|_| outcome.applied.len() > 1) {
+        println!("timings: {}", timings);
+    }
+}
+
+/// Prints queries of the planned migrations, destructive ones are marked with `!`.
+fn print_plan(plan: &[(u64, Vec<String>)]) {
+    if plan.is_empty() {
+        return println!("no migrations found");
+    }
+    for (id, queries) in plan {
+        println!("{}:", id);
+        for query in queries {
+            if vemigrate::is_destructive(query) {
+                println!("  ! {}  <-- DESTRUCTIVE", query);
+            } else {
+                println!("    {}", query);
+            }
+        }
+    }
+}
+
+/// Renders planned migrations as a CQL script with a header listing them,
+/// queries are rewritten the way the store would execute them.
+fn plan_script<F>(plan: &[(u64, Vec<String>)], up: bool, rewrite: F) -> String
+where
+    F: Fn(&str) -> String,
+{
+    let direction = if up { "up" } else { "down" };
+    let mut script = format!("-- Generated by vemigrate-cli, migrations {}:\n", direction);
+    for (id, _) in plan {
+        script.push_str(&format!("--   {}\n", id));
+    }
+    script.push_str("-- Running the script doesn't update the migrations history.\n");
+    for (id, queries) in plan {
+        script.push_str(&format!("\n-- {}\n", id));
+        for query in queries {
+            // Statements parsed with a custom delimiter have no terminator
+            script.push_str(rewrite(query).trim_end().trim_end_matches(';'));
+            script.push_str(";\n");
+        }
+    }
+    script
+}
+
+/// Connects to the database configured by flags.
+fn connect<'a>(cfg: &'a Database, keyspace: &'a str) -> ScyllaStore<'a> {
+    let user = cfg
+        .user
+        .as_deref()
+        .unwrap_or_else(|| fatal_err("database user is required, use --db-user"));
+    let password = if should_prompt_password(stdin_is_tty(), cfg.password.is_some()) {
+        read_hidden("database password: ").unwrap_or_else(fatal_err)
+    } else {
+        cfg.password
+            .clone()
+            .unwrap_or_else(|| fatal_err("database password is required, use --db-password"))
+    };
+    let password = password.as_str();
+    let nodes = match (&cfg.discover_dns, &cfg.node) {
+        (Some(name), _) => discover_nodes(name).unwrap_or_else(fatal_err),
+        (None, Some(node)) => node
+            .split(',')
+            .map(str::trim)
+            .filter(|node| !node.is_empty())
+            .map(String::from)
+            .collect(),
+        (None, None) => fatal_err("database node address is required, use --db-node"),
+    };
+    if cfg.precheck_nodes {
+        let check = check_nodes(&nodes);
+        for node in &check.up {
+            info!("node {} is up", node);
+        }
+        for node in &check.down {
+            warn!("node {} is down", node);
+        }
+        if !check.down.is_empty() {
+            warn!(
+                "{} of {} nodes are down, the cluster is degraded",
+                check.down.len(),
+                nodes.len()
+            );
+        }
+    }
+    let db = match cfg.ssl_ca {
+        Some(ref ca_file) => connect_ssl(&nodes, ca_file, keyspace, user, password, cfg),
+        None => {
+            let lb = LoadBalancing::from_str(&cfg.load_balancing).unwrap();
+            match *nodes.as_slice() {
+                [ref node] => ScyllaStore::with_session(node, keyspace, user, password, lb),
+                _ => ScyllaStore::with_cluster(&nodes, keyspace, user, password, lb),
+            }
+            .unwrap_or_else(fatal_err)
+        }
+    };
+    db.table(&cfg.table)
+        .history_consistency(parse_consistency(&cfg.history_consistency).unwrap())
+        .use_keyspace_check(cfg.use_keyspace_check)
+}
+
+/// Whether the password should be asked for on the terminal: only when none
+/// was given and there is someone to answer.
+fn should_prompt_password(interactive: bool, provided: bool) -> bool {
+    interactive && !provided
+}
+
+#[cfg(unix)]
+fn stdin_is_tty() -> bool {
+    unsafe { libc::isatty(libc::STDIN_FILENO) == 1 }
+}
+
+#[cfg(not(unix))]
+fn stdin_is_tty() -> bool {
+    false
+}
+
+/// Prints `prompt` and reads a line from the terminal without echoing it.
+#[cfg(unix)]
+fn read_hidden(prompt: &str) -> io::Result<String> {
+    let fd = libc::STDIN_FILENO;
+    let mut term = unsafe { std::mem::zeroed::<libc::termios>() };
+    if unsafe { libc::tcgetattr(fd, &mut term) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let saved = term;
+    term.c_lflag &= !libc::ECHO;
+    term.c_lflag |= libc::ECHONL;
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &term) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    eprint!("{}", prompt);
+    let mut line = String::new();
+    let res = io::stderr()
+        .flush()
+        .and_then(|_| io::stdin().read_line(&mut line));
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &saved) };
+    res?;
+    Ok(line.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+#[cfg(not(unix))]
+fn read_hidden(_prompt: &str) -> io::Result<String> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "hidden input is not supported",
+    ))
+}
+
+#[cfg(feature = "ssl")]
+fn connect_ssl<'a>(
+    nodes: &[String],
+    ca_file: &Path,
+    keyspace: &'a str,
+    user: &str,
+    password: &str,
+    cfg: &Database,
+) -> ScyllaStore<'a> {
+    let lb = LoadBalancing::from_str(&cfg.load_balancing).unwrap();
+    ScyllaStore::with_ssl_cluster(nodes, ca_file, keyspace, user, password, lb)
+        .unwrap_or_else(fatal_err)
+}
+
+#[cfg(not(feature = "ssl"))]
+fn connect_ssl<'a>(
+    _nodes: &[String],
+    _ca_file: &Path,
+    _keyspace: &'a str,
+    _user: &str,
+    _password: &str,
+    _cfg: &Database,
+) -> ScyllaStore<'a> {
+    fatal_err("--db-ssl-ca requires vemigrate-cli built with the `ssl` feature")
+}
+
+fn new_migrator<'a, 'k>(
+    path: &'a Path,
+    db: ScyllaStore<'k>,
+    options: &MigratorOptions,
+) -> Migrator<'a, ScyllaStore<'k>> {
+    let mut migrator = Migrator::with_store(path, db).store_content(options.store_content);
+    if let Some(rate) = options.rate_bytes {
+        migrator = migrator.rate_limit(rate);
+    }
+    if let Some(name) = options.applied_by.as_deref() {
+        migrator = migrator.applied_by(name);
+    }
+    migrator
+}
+
+fn with_migrate_args<'a, S: vemigrate::Store>(
+    migrator: Migrator<'a, S>,
+    args: &Migrate,
+) -> Migrator<'a, S> {
+    let migrator = migrator
+        .allow_duplicates(args.allow_duplicates)
+        .only_failed(args.only_failed)
+        .verify_checksums(!args.ignore_checksums)
+        .require_initialized(args.require_initialized);
+    let migrator = match args.max_version {
+        Some(version) => migrator.max_version(version),
+        None => migrator,
+    };
+    match args.tag {
+        Some(ref tag) => migrator.only_tag(tag.as_str()),
+        None => migrator,
+    }
+}
+
+/// Migrates up the keyspace of every tenant in turn, migrations referring to
+/// the configured keyspace are executed against the keyspace of the tenant.
+/// Stops at the first failure, so that a broken migration doesn't spread.
+fn migrate_tenants(
+    cfg: &Database,
+    path: &Path,
+    options: &MigratorOptions,
+    args: &Migrate,
+    tenants: &[String],
+    quiet: bool,
+) {
+    // Keyspaces are checked before migrating any tenant
+    let keyspaces: Vec<String> = tenants
+        .iter()
+        .map(|tenant| tenant_keyspace(&cfg.keyspace, tenant).unwrap_or_else(fatal_err))
+        .collect();
+    // Credentials are resolved and the session is opened once for all tenants
+    let session = connect(cfg, &cfg.keyspace);
+    for (tenant, keyspace) in tenants.iter().zip(&keyspaces) {
+        info!(
+            "execute pending migrations of tenant {} in keyspace {}",
+            tenant, keyspace
+        );
+        let db = session
+            .with_keyspace(keyspace)
+            .rewrite_keyspace(&cfg.keyspace);
+        let migrator = with_migrate_args(new_migrator(path, db, options), args);
+        if args.dry_run {
+            println!("tenant {}:", tenant);
+            print_plan(&migrator.plan(true, None).unwrap_or_else(fatal_err));
+            continue;
+        }
+        match migrator.migrate_outcome(true, None) {
+            Ok(Some(outcome)) => {
+                if !quiet {
+                    println!("tenant {}:", tenant);
+                }
+                print_outcome(&outcome, quiet)
+            }
+            Ok(None) => info!("no pending migrations found for tenant {}", tenant),
+            Err(err) => fatal_err(format!("tenant {}: {}", tenant, err)),
+        };
+    }
+}
+
+/// Returns the keyspace of the tenant, which is used unquoted in queries,
+/// so the id may only contain letters, digits and underscores.
+fn tenant_keyspace(keyspace: &str, tenant: &str) -> Result<String, String> {
+    if tenant.is_empty()
+        || !tenant
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Err(format!(
+            "tenant id '{}' may only contain letters, digits and underscores",
+            tenant
+        ));
+    }
+    let tenant_keyspace = format!("{}_{}", keyspace, tenant);
+    if tenant_keyspace.len() > MAX_KEYSPACE_NAME_LENGTH {
+        return Err(format!(
+            "keyspace '{}' of tenant {} is longer than {} characters",
+            tenant_keyspace, tenant, MAX_KEYSPACE_NAME_LENGTH
+        ));
+    }
+    Ok(tenant_keyspace)
+}
+
+/// Returns a unique name of the keyspace to try migrations in,
+/// shortened to the maximum length of keyspace names.
+fn shadow_keyspace_name(keyspace: &str) -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("get unix timestamp")
+        .subsec_nanos();
+    let suffix = format!("_shadow_{}", nanos);
+    let prefix_len = keyspace.len().min(MAX_KEYSPACE_NAME_LENGTH - suffix.len());
+    format!("{}{}", &keyspace[..prefix_len], suffix)
+}
+
 fn create_migrations_dir(path: &PathBuf) -> std::io::Result<()> {
     println!("creating migrations directory at: {}", path.display());
-    fs::create_dir(&path)?;
+    fs::create_dir(path)?;
     Ok(())
 }
 
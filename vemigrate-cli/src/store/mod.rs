@@ -1,5 +1,7 @@
 mod scylla_store;
 
 pub use scylla_store::{
-    ReplicationStrategy, ScyllaStore, NETWORK_TOPOLOGY_STRATEGY, SIMPLE_STRATEGY,
+    check_nodes, discover_nodes, parse_consistency, LoadBalancing, ReplicationStrategy,
+    ScyllaStore, CONSISTENCY_LEVELS, DEFAULT_HISTORY_TABLE, NETWORK_TOPOLOGY_STRATEGY, RANDOM,
+    ROUND_ROBIN, ROUND_ROBIN_SYNC, SIMPLE_STRATEGY, SINGLE_NODE,
 };
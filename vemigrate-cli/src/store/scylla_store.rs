@@ -1,20 +1,107 @@
 use cdrs::authenticators::StaticPasswordAuthenticator;
+#[cfg(feature = "ssl")]
+use cdrs::cluster::session::new_ssl as new_ssl_session;
 use cdrs::cluster::session::{new as new_session, Session};
+#[cfg(feature = "ssl")]
+use cdrs::cluster::{ClusterSslConfig, NodeSslConfigBuilder, SslConnectionPool};
 use cdrs::cluster::{ClusterTcpConfig, NodeTcpConfigBuilder, TcpConnectionPool};
-use cdrs::load_balancing::RoundRobin;
+use cdrs::consistency::Consistency;
+use cdrs::load_balancing::{LoadBalancingStrategy, Random, RoundRobin, RoundRobinSync, SingleNode};
 use cdrs::types::from_cdrs::FromCDRSByName;
 use cdrs::types::prelude::*;
+use cdrs::types::IntoRustByName;
 use cdrs::Result as CDRSResult;
-use vemigrate::{self, MigrationRow, Store};
+use vemigrate::{self, HistoryEvent, MigrationRow, Store};
 
-use cdrs::query::QueryExecutor;
+use cdrs::frame::frame_error::AdditionalErrorInfo;
+use cdrs::frame::frame_result::RowsMetadataFlag;
+use cdrs::frame::Frame;
+use cdrs::query::{QueryExecutor, QueryParams, QueryParamsBuilder, QueryValues};
+#[cfg(feature = "ssl")]
+use openssl::ssl::{SslConnector, SslMethod};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::{self, Display, Formatter};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+#[cfg(feature = "ssl")]
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::{error, io};
+use std::{process, thread};
 
 pub const SIMPLE_STRATEGY: &str = "SimpleStrategy";
 pub const NETWORK_TOPOLOGY_STRATEGY: &str = "NetworkTopologyStrategy";
 
+pub const ROUND_ROBIN: &str = "round-robin";
+pub const ROUND_ROBIN_SYNC: &str = "round-robin-sync";
+pub const RANDOM: &str = "random";
+pub const SINGLE_NODE: &str = "single-node";
+
+/// Consistency levels accepted by `parse_consistency`.
+pub const CONSISTENCY_LEVELS: &[&str] = &[
+    "any",
+    "one",
+    "two",
+    "three",
+    "quorum",
+    "all",
+    "local-quorum",
+    "each-quorum",
+    "local-one",
+];
+
+const DEFAULT_PORT: u16 = 9042;
+
+/// Placeholder in migrations replaced with the keyspace of the store,
+/// e.g. to run the same migrations in keyspaces of many tenants.
+const KEYSPACE_VARIABLE: &str = "{{keyspace}}";
+
+/// Name of the migrations table unless configured otherwise.
+pub const DEFAULT_HISTORY_TABLE: &str = "migrations";
+
+// Columns of the migrations table missing in tables created by older versions
+const HISTORY_SEQUENCE_COLUMN: &str = "seq";
+const HISTORY_NAME_COLUMN: &str = "name";
+const HISTORY_APPLIED_AT_COLUMN: &str = "applied_at";
+const HISTORY_DURATION_COLUMN: &str = "duration_ms";
+const HISTORY_APPLIED_BY_COLUMN: &str = "applied_by";
+const HISTORY_CONTENT_COLUMN: &str = "content";
+const HISTORY_CHECKSUM_COLUMN: &str = "checksum";
+const HISTORY_DOWN_CHECKSUM_COLUMN: &str = "down_checksum";
+const HISTORY_OPTIONAL_COLUMNS: &[(&str, &str)] = &[
+    (HISTORY_SEQUENCE_COLUMN, "bigint"),
+    (HISTORY_NAME_COLUMN, "text"),
+    (HISTORY_APPLIED_AT_COLUMN, "timestamp"),
+    (HISTORY_DURATION_COLUMN, "bigint"),
+    (HISTORY_APPLIED_BY_COLUMN, "text"),
+    (HISTORY_CONTENT_COLUMN, "text"),
+    (HISTORY_CHECKSUM_COLUMN, "text"),
+    (HISTORY_DOWN_CHECKSUM_COLUMN, "text"),
+];
+
+/// Rows of the history fetched per page, the history is read until the last page.
+const HISTORY_PAGE_SIZE: i32 = 1000;
+
+/// Time to wait for a node to accept a connection when some nodes may be down.
+const NODE_PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Row of the locks table taken by a migrator while it migrates.
+const LOCK_NAME: &str = "migrate";
+/// Time after which a lock left by a crashed migrator expires.
+const LOCK_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Largest migrations executed in one batch with the history insert, larger
+/// ones would hit `batch_size_warn_threshold` or even the fail threshold.
+const BATCH_MAX_STATEMENTS: usize = 16;
+const BATCH_MAX_BYTES: usize = 5 * 1024;
+
+const BOOTSTRAP_RETRIES: usize = 5;
+const BOOTSTRAP_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+#[derive(Default)]
 pub enum ReplicationStrategy {
+    #[default]
     Simple,
     NetworkTopology,
 }
@@ -29,12 +116,6 @@ impl ReplicationStrategy {
     }
 }
 
-impl Default for ReplicationStrategy {
-    fn default() -> Self {
-        ReplicationStrategy::Simple
-    }
-}
-
 impl Display for ReplicationStrategy {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
@@ -44,12 +125,161 @@ impl Display for ReplicationStrategy {
     }
 }
 
+/// Load balancing strategies supported by cdrs, which choose the node for every query.
+pub enum LoadBalancing<N> {
+    RoundRobin(RoundRobin<N>),
+    RoundRobinSync(RoundRobinSync<N>),
+    Random(Random<N>),
+    /// Always uses the first node.
+    SingleNode(SingleNode<N>),
+}
+
+impl<N> LoadBalancing<N> {
+    pub fn from_str(val: &str) -> Option<Self> {
+        match val {
+            ROUND_ROBIN => Some(LoadBalancing::RoundRobin(RoundRobin::new())),
+            ROUND_ROBIN_SYNC => Some(LoadBalancing::RoundRobinSync(RoundRobinSync::new())),
+            RANDOM => Some(LoadBalancing::Random(Random::new(Vec::new()))),
+            SINGLE_NODE => Some(LoadBalancing::SingleNode(SingleNode::new())),
+            _ => None,
+        }
+    }
+}
+
+/// Parses one of `CONSISTENCY_LEVELS`.
+pub fn parse_consistency(val: &str) -> Option<Consistency> {
+    match val {
+        "any" => Some(Consistency::Any),
+        "one" => Some(Consistency::One),
+        "two" => Some(Consistency::Two),
+        "three" => Some(Consistency::Three),
+        "quorum" => Some(Consistency::Quorum),
+        "all" => Some(Consistency::All),
+        "local-quorum" => Some(Consistency::LocalQuorum),
+        "each-quorum" => Some(Consistency::EachQuorum),
+        "local-one" => Some(Consistency::LocalOne),
+        _ => None,
+    }
+}
+
+impl<N> Default for LoadBalancing<N> {
+    fn default() -> Self {
+        LoadBalancing::RoundRobin(RoundRobin::new())
+    }
+}
+
+impl<N> LoadBalancingStrategy<N> for LoadBalancing<N> {
+    fn init(&mut self, cluster: Vec<N>) {
+        match self {
+            LoadBalancing::RoundRobin(lb) => lb.init(cluster),
+            LoadBalancing::RoundRobinSync(lb) => lb.init(cluster),
+            LoadBalancing::Random(lb) => lb.init(cluster),
+            LoadBalancing::SingleNode(lb) => lb.init(cluster),
+        }
+    }
+
+    fn next(&self) -> Option<&N> {
+        match self {
+            LoadBalancing::RoundRobin(lb) => lb.next(),
+            LoadBalancing::RoundRobinSync(lb) => lb.next(),
+            LoadBalancing::Random(lb) => lb.next(),
+            LoadBalancing::SingleNode(lb) => lb.next(),
+        }
+    }
+}
+
+/// Resolves a DNS name into node addresses using the given resolver,
+/// the default CQL port is used if the name has no port.
+pub fn resolve_contact_points<F, I>(name: &str, resolve: F) -> Result<Vec<String>>
+where
+    F: FnOnce(&str) -> io::Result<I>,
+    I: Iterator<Item = SocketAddr>,
+{
+    let name = if name.contains(':') {
+        name.to_string()
+    } else {
+        format!("{}:{}", name, DEFAULT_PORT)
+    };
+    let addrs: Vec<String> = resolve(&name)
+        .map_err(|err| Error::Discovery(format!("failed to resolve {}: {}", name, err)))?
+        .map(|addr| addr.to_string())
+        .collect();
+    if addrs.is_empty() {
+        return Err(Error::Discovery(format!(
+            "{} resolved to no addresses",
+            name
+        )));
+    }
+    Ok(addrs)
+}
+
+/// Returns addresses of nodes accepting TCP connections, or all of them
+/// if there is only one or none is reachable, so that the session reports
+/// the error of connecting.
+fn reachable_nodes(addrs: &[String]) -> Vec<&String> {
+    if addrs.len() < 2 {
+        return addrs.iter().collect();
+    }
+    let reachable: Vec<&String> = addrs
+        .iter()
+        .filter(|addr| {
+            let reachable = is_reachable(addr);
+            if !reachable {
+                warn!("node {} is unreachable, skipping it", addr);
+            }
+            reachable
+        })
+        .collect();
+    if reachable.is_empty() {
+        return addrs.iter().collect();
+    }
+    reachable
+}
+
+/// Checks whether the node accepts TCP connections.
+fn is_reachable(addr: &str) -> bool {
+    addr.to_socket_addrs()
+        .into_iter()
+        .flatten()
+        .any(|addr| TcpStream::connect_timeout(&addr, NODE_PROBE_TIMEOUT).is_ok())
+}
+
+/// Addresses of nodes split by reachability, see `check_nodes`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NodesCheck {
+    pub up: Vec<String>,
+    pub down: Vec<String>,
+}
+
+/// Tries to connect to every node separately, unlike a session which only
+/// needs one of them, e.g. to tell that the cluster is degraded.
+pub fn check_nodes(addrs: &[String]) -> NodesCheck {
+    check_nodes_with(addrs, is_reachable)
+}
+
+fn check_nodes_with<F>(addrs: &[String], is_reachable: F) -> NodesCheck
+where
+    F: Fn(&str) -> bool,
+{
+    let (up, down) = addrs.iter().cloned().partition(|addr| is_reachable(addr));
+    NodesCheck { up, down }
+}
+
+/// Resolves a DNS name into node addresses with the system resolver.
+pub fn discover_nodes(name: &str) -> Result<Vec<String>> {
+    resolve_contact_points(name, |name| name.to_socket_addrs())
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug)]
 pub enum Error {
     Database(cdrs::Error),
     Io(io::Error),
+    Discovery(String),
+    InvalidTableOptions(String),
+    Tls(String),
+    Lock(String),
 }
 
 impl error::Error for Error {}
@@ -59,6 +289,12 @@ impl fmt::Display for Error {
         match self {
             Error::Io(ref e) => e.fmt(f),
             Error::Database(ref e) => e.fmt(f),
+            Error::Discovery(ref e) => f.write_str(e),
+            Error::InvalidTableOptions(ref options) => {
+                write!(f, "table options must start with WITH, got '{}'", options)
+            }
+            Error::Tls(ref e) => write!(f, "TLS error: {}", e),
+            Error::Lock(ref e) => f.write_str(e),
         }
     }
 }
@@ -75,60 +311,1095 @@ impl From<cdrs::Error> for Error {
     }
 }
 
-impl Into<vemigrate::Error> for Error {
-    fn into(self) -> vemigrate::Error {
-        vemigrate::Error::Store(Box::new(self))
+impl From<Error> for vemigrate::Error {
+    fn from(err: Error) -> Self {
+        vemigrate::Error::Store(Box::new(err))
     }
 }
 
-type ScyllaSession = Session<RoundRobin<TcpConnectionPool<StaticPasswordAuthenticator>>>;
+pub type NodePool = TcpConnectionPool<StaticPasswordAuthenticator>;
+pub type ScyllaSession = Session<LoadBalancing<NodePool>>;
+#[cfg(feature = "ssl")]
+pub type SslNodePool = SslConnectionPool<StaticPasswordAuthenticator>;
+#[cfg(feature = "ssl")]
+pub type ScyllaSslSession = Session<LoadBalancing<SslNodePool>>;
+
+enum Connection {
+    Tcp(ScyllaSession),
+    #[cfg(feature = "ssl")]
+    Ssl(ScyllaSslSession),
+}
+
+/// Evaluates the expression with the session of the connection, whichever it is.
+macro_rules! with_session {
+    ($conn:expr, |$session:ident| $body:expr) => {
+        match $conn {
+            Connection::Tcp(ref $session) => $body,
+            #[cfg(feature = "ssl")]
+            Connection::Ssl(ref $session) => $body,
+        }
+    };
+}
+
+// Queries used by the store, which are the same for both kinds of sessions
+impl Connection {
+    fn query_tw<Q: ToString>(
+        &self,
+        query: Q,
+        with_tracing: bool,
+        with_warnings: bool,
+    ) -> CDRSResult<Frame> {
+        with_session!(*self, |conn| conn.query_tw(
+            query,
+            with_tracing,
+            with_warnings
+        ))
+    }
+
+    fn query_with_values_tw<Q: ToString, V: Into<QueryValues>>(
+        &self,
+        query: Q,
+        values: V,
+        with_tracing: bool,
+        with_warnings: bool,
+    ) -> CDRSResult<Frame> {
+        with_session!(*self, |conn| conn.query_with_values_tw(
+            query,
+            values,
+            with_tracing,
+            with_warnings
+        ))
+    }
+
+    fn query_with_params<Q: ToString>(&self, query: Q, params: QueryParams) -> CDRSResult<Frame> {
+        with_session!(*self, |conn| conn.query_with_params(query, params))
+    }
+}
 
 pub struct ScyllaStore<'a> {
-    conn: ScyllaSession,
+    // Shared by stores of other keyspaces created with `with_keyspace`
+    conn: Rc<Connection>,
     keyspace: &'a str,
+    table: &'a str,
+    // Set once the migrations table was created by this store
+    bootstrapped: AtomicBool,
+    // Identifies this store in the locks table, set while it holds the lock
+    lock_owner: String,
+    locked: AtomicBool,
+    // Set while the lock waits for the initial migration to create the keyspace
+    lock_deferred: AtomicBool,
+    // Set once the failures table is known to exist
+    failures_table: AtomicBool,
+    // Keyspace referred by migrations which is replaced with `keyspace`
+    rewritten_keyspace: Option<String>,
+    history_consistency: Consistency,
+    // Checks the keyspace with `use` and tables with a select instead of reading `system_schema`
+    use_keyspace_check: bool,
 }
 
 impl<'a> ScyllaStore<'a> {
-    pub fn with_session(addr: &str, keyspace: &'a str, user: &str, password: &str) -> Result<Self> {
+    pub fn with_session(
+        addr: &str,
+        keyspace: &'a str,
+        user: &str,
+        password: &str,
+        load_balancing: LoadBalancing<NodePool>,
+    ) -> Result<Self> {
+        Self::with_cluster(
+            &[addr.to_string()],
+            keyspace,
+            user,
+            password,
+            load_balancing,
+        )
+    }
+
+    /// Creates a session with one contact point per node address.
+    /// Nodes not accepting connections are skipped, so that it's enough
+    /// for one of them to be up.
+    pub fn with_cluster(
+        addrs: &[String],
+        keyspace: &'a str,
+        user: &str,
+        password: &str,
+        load_balancing: LoadBalancing<NodePool>,
+    ) -> Result<Self> {
         let auth = StaticPasswordAuthenticator::new(user, password);
-        let nodes = vec![NodeTcpConfigBuilder::new(addr, auth).build()];
+        let nodes = reachable_nodes(addrs)
+            .iter()
+            .map(|addr| NodeTcpConfigBuilder::new(addr, auth.clone()).build())
+            .collect();
         let cluster_config = ClusterTcpConfig(nodes);
 
-        let conn = new_session(&cluster_config, RoundRobin::new())?;
-        Ok(Self { conn, keyspace })
+        let conn = new_session(&cluster_config, load_balancing)?;
+        Ok(Self::from_session(conn, keyspace))
+    }
+
+    /// Creates a session over TLS with one contact point per node address,
+    /// certificates of nodes are verified with the CA certificate file.
+    #[cfg(feature = "ssl")]
+    pub fn with_ssl_cluster(
+        addrs: &[String],
+        ca_file: &Path,
+        keyspace: &'a str,
+        user: &str,
+        password: &str,
+        load_balancing: LoadBalancing<SslNodePool>,
+    ) -> Result<Self> {
+        let mut builder =
+            SslConnector::builder(SslMethod::tls()).map_err(|err| Error::Tls(err.to_string()))?;
+        builder.set_ca_file(ca_file).map_err(|err| {
+            Error::Tls(format!(
+                "failed to read CA certificate {}: {}",
+                ca_file.display(),
+                err
+            ))
+        })?;
+        let connector = builder.build();
+
+        let auth = StaticPasswordAuthenticator::new(user, password);
+        let nodes = reachable_nodes(addrs)
+            .iter()
+            .map(|addr| NodeSslConfigBuilder::new(addr, auth.clone(), connector.clone()).build())
+            .collect();
+        let cluster_config = ClusterSslConfig(nodes);
+
+        let conn = new_ssl_session(&cluster_config, load_balancing)?;
+        Ok(Self::with_connection(Connection::Ssl(conn), keyspace))
+    }
+
+    /// Wraps a session configured by the caller, e.g. to reuse its connections.
+    pub fn from_session(conn: ScyllaSession, keyspace: &'a str) -> Self {
+        Self::with_connection(Connection::Tcp(conn), keyspace)
+    }
+
+    fn with_connection(conn: Connection, keyspace: &'a str) -> Self {
+        Self::with_shared_connection(Rc::new(conn), keyspace)
+    }
+
+    fn with_shared_connection(conn: Rc<Connection>, keyspace: &'a str) -> Self {
+        Self {
+            conn,
+            keyspace,
+            table: DEFAULT_HISTORY_TABLE,
+            bootstrapped: AtomicBool::new(false),
+            lock_owner: format!(
+                "{}-{}",
+                process::id(),
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .expect("get unix timestamp")
+                    .as_nanos()
+            ),
+            locked: AtomicBool::new(false),
+            lock_deferred: AtomicBool::new(false),
+            failures_table: AtomicBool::new(false),
+            rewritten_keyspace: None,
+            history_consistency: Consistency::Quorum,
+            use_keyspace_check: false,
+        }
+    }
+
+    /// Returns a store of another keyspace with the session and the settings
+    /// of this one, e.g. to migrate a keyspace per tenant over a single session.
+    pub fn with_keyspace<'b>(&self, keyspace: &'b str) -> ScyllaStore<'b>
+    where
+        'a: 'b,
+    {
+        ScyllaStore {
+            table: self.table,
+            rewritten_keyspace: self.rewritten_keyspace.clone(),
+            history_consistency: self.history_consistency,
+            use_keyspace_check: self.use_keyspace_check,
+            ..ScyllaStore::with_shared_connection(Rc::clone(&self.conn), keyspace)
+        }
+    }
+
+    /// Executes queries referring to the given keyspace against the keyspace
+    /// of the store instead, e.g. to try migrations on a copy.
+    pub fn rewrite_keyspace(mut self, keyspace: &str) -> Self {
+        self.rewritten_keyspace = Some(keyspace.to_string());
+        self
+    }
+
+    /// Keeps the history in the given table of the keyspace instead of `migrations`,
+    /// e.g. to adopt a table of another tool.
+    pub fn table(mut self, table: &'a str) -> Self {
+        self.table = table;
+        self
+    }
+
+    /// Sets consistency of history reads and writes, `QUORUM` by default, so that
+    /// another migrator sees migrations as applied as soon as they are.
+    /// Migrations themselves are executed with the default consistency.
+    pub fn history_consistency(mut self, consistency: Consistency) -> Self {
+        self.history_consistency = consistency;
+        self
+    }
+
+    /// Checks whether the keyspace exists by switching to it with `use`, a keyspace
+    /// which doesn't exist means the database is not initialized. Columns of the
+    /// history tables are read from the metadata of a select instead of
+    /// `system_schema` too, which suits roles without access to it.
+    /// `dump_schema` still reads `system_schema`.
+    pub fn use_keyspace_check(mut self, enabled: bool) -> Self {
+        self.use_keyspace_check = enabled;
+        self
+    }
+
+    /// Drops the keyspace of the store with everything in it.
+    pub fn drop_keyspace(&self) -> Result<()> {
+        debug!("drop keyspace {}", self.keyspace);
+        self.conn
+            .query_tw(
+                format!("drop keyspace if exists {};", self.keyspace),
+                false,
+                false,
+            )
+            .map_err(Error::from)
+            .map(|_| ())
+    }
+
+    pub fn keyspace_exists(&self) -> Result<bool> {
+        if self.use_keyspace_check {
+            return match self
+                .conn
+                .query_tw(format!("use {};", self.keyspace), false, false)
+            {
+                Ok(_) => Ok(true),
+                Err(ref err) if is_missing_keyspace(err) => Ok(false),
+                Err(err) => Err(err.into()),
+            };
+        }
+        let rows = self
+            .conn
+            .query_with_values_tw(
+                "select * from system_schema.keyspaces where keyspace_name = ?;",
+                query_values!(self.keyspace),
+                false,
+                false,
+            )?
+            .get_body()?
+            .into_rows();
+        Ok(rows.is_some_and(|rows| !rows.is_empty()))
+    }
+
+    fn table_exists(&self, table: &str) -> Result<bool> {
+        if self.use_keyspace_check {
+            return Ok(self.selected_columns(table)?.is_some());
+        }
+        let rows = self
+            .conn
+            .query_with_values_tw(
+                "select table_name from system_schema.tables \
+                 where keyspace_name = ? and table_name = ?;",
+                query_values!(self.keyspace, table),
+                false,
+                false,
+            )?
+            .get_body()?
+            .into_rows();
+        Ok(rows.is_some_and(|rows| !rows.is_empty()))
+    }
+
+    /// Returns the insert into the history with its values, only columns
+    /// present in the table are set.
+    fn history_insert(&self, event: &HistoryEvent) -> Result<(String, Vec<Value>)> {
+        let mut columns = vec!["id", "up"];
+        let mut values: Vec<Value> = vec![event.id.into(), event.up.into()];
+        for column in self.optional_history_columns()? {
+            match column {
+                HISTORY_SEQUENCE_COLUMN => {
+                    let seq = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .expect("get unix timestamp")
+                        .as_micros() as i64;
+                    values.push(seq.into());
+                }
+                HISTORY_NAME_COLUMN => match event.name {
+                    Some(ref name) => values.push(name.as_str().into()),
+                    None => continue,
+                },
+                HISTORY_APPLIED_AT_COLUMN => match event.applied_at {
+                    Some(time) => values.push(timestamp_millis(time).into()),
+                    None => continue,
+                },
+                HISTORY_DURATION_COLUMN => match event.duration {
+                    Some(duration) => values.push((duration.as_millis() as i64).into()),
+                    None => continue,
+                },
+                HISTORY_APPLIED_BY_COLUMN => match event.applied_by {
+                    Some(ref name) => values.push(name.as_str().into()),
+                    None => continue,
+                },
+                HISTORY_CONTENT_COLUMN => match event.content {
+                    Some(ref content) => values.push(content.as_str().into()),
+                    None => continue,
+                },
+                HISTORY_CHECKSUM_COLUMN => match event.checksum {
+                    Some(ref checksum) => values.push(checksum.as_str().into()),
+                    None => continue,
+                },
+                HISTORY_DOWN_CHECKSUM_COLUMN => match event.down_checksum {
+                    Some(ref checksum) => values.push(checksum.as_str().into()),
+                    None => continue,
+                },
+                _ => continue,
+            }
+            columns.push(column);
+        }
+
+        let placeholders = vec!["?"; columns.len()].join(", ");
+        let insert = format!(
+            "insert into {}.{} ({}) values ({});",
+            self.keyspace,
+            self.table,
+            columns.join(","),
+            placeholders
+        );
+        Ok((insert, values))
+    }
+
+    /// Executes the query with the consistency of the history.
+    fn query_history(&self, query: String, values: Vec<Value>) -> Result<()> {
+        let params = QueryParamsBuilder::new()
+            .consistency(self.history_consistency)
+            .values(QueryValues::SimpleValues(values))
+            .finalize();
+        self.conn
+            .query_with_params(query, params)
+            .map_err(Error::from)
+            .map(|_| ())
+    }
+
+    fn insert_lock(&self) -> Result<bool> {
+        debug!("acquire lock {} as {}", LOCK_NAME, self.lock_owner);
+        self.conn
+            .query_tw(Self::locks_table_up(self.keyspace), false, false)?;
+        let params = QueryParamsBuilder::new()
+            .consistency(self.history_consistency)
+            .values(query_values!(LOCK_NAME, self.lock_owner.as_str()))
+            .finalize();
+        let row = self
+            .conn
+            .query_with_params(
+                format!(
+                    "insert into {}.migration_locks (name, owner, locked_at) \
+                     values (?, ?, toTimestamp(now())) if not exists using ttl {};",
+                    self.keyspace,
+                    LOCK_TTL.as_secs()
+                ),
+                params,
+            )?
+            .get_body()?
+            .into_rows()
+            .and_then(|rows| rows.into_iter().next())
+            .ok_or_else(|| Error::Database("lock insert should yield a row".into()))?;
+
+        let applied: bool = row.get_r_by_name("[applied]")?;
+        if applied {
+            self.locked.store(true, Ordering::Relaxed);
+        } else {
+            let owner: Option<String> = row.get_by_name("owner")?;
+            warn!(
+                "lock {} is held by {}",
+                LOCK_NAME,
+                owner.as_deref().unwrap_or("unknown")
+            );
+        }
+        Ok(applied)
+    }
+
+    /// Deletes the failure of the migration once it succeeded, so that
+    /// `only_failed` doesn't retry it after it's rolled back. A keyspace
+    /// created by the run has no failures.
+    fn clear_failure(&self, id: u64) -> Result<()> {
+        if !self.failures_table.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let params = QueryParamsBuilder::new()
+            .consistency(self.history_consistency)
+            .values(query_values!(id as i64))
+            .finalize();
+        self.conn
+            .query_with_params(
+                format!(
+                    "delete from {}.migration_failures where id = ?;",
+                    self.keyspace
+                ),
+                params,
+            )
+            .map_err(Error::from)
+            .map(|_| ())
+    }
+
+    /// Takes the lock deferred by `acquire_lock` once the initial migration
+    /// created the keyspace, or renews the TTL of the held lock. Fails if
+    /// another migrator took the lock meanwhile, or it expired.
+    fn hold_lock(&self) -> Result<()> {
+        if self.lock_deferred.swap(false, Ordering::Relaxed) {
+            if !self.insert_lock()? {
+                return Err(Error::Lock(format!(
+                    "lock {} was taken by another migrator while creating the keyspace",
+                    LOCK_NAME
+                )));
+            }
+            return Ok(());
+        }
+        if !self.locked.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        debug!("renew lock {}", LOCK_NAME);
+        let params = QueryParamsBuilder::new()
+            .consistency(self.history_consistency)
+            .values(query_values!(
+                self.lock_owner.as_str(),
+                LOCK_NAME,
+                self.lock_owner.as_str()
+            ))
+            .finalize();
+        let applied: bool = self
+            .conn
+            .query_with_params(
+                format!(
+                    "update {}.migration_locks using ttl {} set owner = ? \
+                     where name = ? if owner = ?;",
+                    self.keyspace,
+                    LOCK_TTL.as_secs()
+                ),
+                params,
+            )?
+            .get_body()?
+            .into_rows()
+            .and_then(|rows| rows.into_iter().next())
+            .ok_or_else(|| Error::Database("lock update should yield a row".into()))?
+            .get_r_by_name("[applied]")?;
+        if !applied {
+            self.locked.store(false, Ordering::Relaxed);
+            return Err(Error::Lock(format!(
+                "lock {} expired or was taken by another migrator",
+                LOCK_NAME
+            )));
+        }
+        Ok(())
+    }
+
+    /// Substitutes the keyspace variable and rewrites the keyspace of the query.
+    pub fn rewrite_query(&self, q: &str) -> String {
+        let mut rewritten = q.replace(KEYSPACE_VARIABLE, self.keyspace);
+        if let Some(ref from) = self.rewritten_keyspace {
+            rewritten = replace_keyspace(&rewritten, from, self.keyspace);
+        }
+        rewritten
+    }
+
+    /// Returns names of columns of the table from the metadata of a select,
+    /// None if the table doesn't exist.
+    fn selected_columns(&self, table: &str) -> Result<Option<Vec<String>>> {
+        let query = format!("select * from {}.{} limit 1;", self.keyspace, table);
+        let frame = match self.conn.query_tw(query, false, false) {
+            Ok(frame) => frame,
+            Err(ref err) if is_missing_table(err) || is_missing_keyspace(err) => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let columns = frame
+            .get_body()?
+            .as_rows_metadata()
+            .map(|metadata| {
+                metadata
+                    .col_specs
+                    .iter()
+                    .map(|spec| spec.name.as_str().to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(Some(columns))
+    }
+
+    /// Returns names of columns of the migrations table.
+    fn history_columns(&self) -> Result<Vec<String>> {
+        if self.use_keyspace_check {
+            return Ok(self.selected_columns(self.table)?.unwrap_or_default());
+        }
+        let rows = self
+            .conn
+            .query_with_values_tw(
+                "select column_name from system_schema.columns \
+                 where keyspace_name = ? and table_name = ?;",
+                query_values!(self.keyspace, self.table),
+                false,
+                false,
+            )?
+            .get_body()?
+            .into_rows()
+            .unwrap_or_default();
+        Ok(rows
+            .into_iter()
+            .map(|row| row.get_r_by_name("column_name"))
+            .collect::<CDRSResult<Vec<String>>>()?)
+    }
+
+    /// Returns optional columns which exist in the migrations table.
+    fn optional_history_columns(&self) -> Result<Vec<&'static str>> {
+        let columns = self.history_columns()?;
+        Ok(HISTORY_OPTIONAL_COLUMNS
+            .iter()
+            .map(|(name, _)| *name)
+            .filter(|name| columns.iter().any(|c| c == name))
+            .collect())
+    }
+
+    /// Returns statements adding optional columns missing in the migrations
+    /// table with the given columns.
+    fn missing_columns_ddl(&self, columns: &[String]) -> Vec<String> {
+        HISTORY_OPTIONAL_COLUMNS
+            .iter()
+            .filter(|(name, _)| !columns.iter().any(|c| c == name))
+            .map(|(name, column_type)| {
+                format!(
+                    "alter table {}.{} add {} {};",
+                    self.keyspace, self.table, name, column_type
+                )
+            })
+            .collect()
+    }
+
+    fn select_history(&self) -> Result<Option<Vec<Migration>>> {
+        let optional_columns = self.optional_history_columns()?;
+        let mut columns = vec!["id", "up"];
+        columns.extend(&optional_columns);
+        let columns = columns.join(", ");
+        let query = format!("select {} from {}.{}", columns, self.keyspace, self.table);
+
+        // Paged by hand, as the session pager always reads with the default consistency
+        let mut rows = Vec::new();
+        let mut paging_state = None;
+        loop {
+            let mut params = QueryParamsBuilder::new()
+                .consistency(self.history_consistency)
+                .page_size(HISTORY_PAGE_SIZE);
+            if let Some(state) = paging_state.take() {
+                params = params.paging_state(state);
+            }
+            let body = self
+                .conn
+                .query_with_params(&query, params.finalize())?
+                .get_body()?;
+            let metadata = body
+                .as_rows_metadata()
+                .ok_or_else(|| Error::Database("history query should yield rows".into()))?;
+            rows.extend(body.into_rows().unwrap_or_default());
+            match metadata.paging_state {
+                Some(state) if RowsMetadataFlag::has_has_more_pages(metadata.flags) => {
+                    paging_state = Some(state);
+                }
+                _ => break,
+            }
+            debug!("fetch the next page of the history");
+        }
+
+        if rows.is_empty() {
+            debug!("no migrations found in history");
+            return Ok(None);
+        }
+        let mut migrations = rows
+            .into_iter()
+            .map(|row| Migration::from_row(row, &optional_columns))
+            .collect::<CDRSResult<Vec<Migration>>>()?;
+        // The only row of a migration without `seq` is the latest event kept by
+        // a `primary key(id)` table before `seq` was added, older than the others
+        let mut rows_per_id = HashMap::new();
+        for m in &migrations {
+            *rows_per_id.entry(m.id).or_insert(0) += 1;
+        }
+        for m in &mut migrations {
+            if m.seq.is_none() && rows_per_id[&m.id] == 1 {
+                m.seq = Some(0);
+            }
+        }
+        Ok(Some(migrations))
+    }
+
+    /// Returns `create table` statements of all tables of the keyspace,
+    /// rebuilt from the live `system_schema`. Indexes, views, types and
+    /// table options are not included.
+    pub fn dump_schema(&self) -> Result<String> {
+        debug!("select columns of keyspace {}", self.keyspace);
+        let rows = self
+            .conn
+            .query_with_values_tw(
+                "select table_name, column_name, kind, position, clustering_order, type as column_type \
+                 from system_schema.columns where keyspace_name = ?;",
+                query_values!(self.keyspace),
+                false,
+                false,
+            )?
+            .get_body()?
+            .into_rows()
+            .unwrap_or_default()
+            .into_iter()
+            .map(ColumnDefinition::try_from_row)
+            .collect::<CDRSResult<Vec<ColumnDefinition>>>()?;
+        Ok(tables_ddl(self.keyspace, &rows))
+    }
+
+    /// Checks whether the query creates the migrations table.
+    fn is_bootstrap_query(&self, q: &str) -> bool {
+        let q = q.to_lowercase();
+        q.starts_with("create table")
+            && q.contains(&format!("{}.{}", self.keyspace, self.table).to_lowercase())
     }
 
     pub fn initial_migration_up(
         keyspace: &str,
+        table: &str,
         replication_strategy: ReplicationStrategy,
         replication_factor: usize,
+        table_options: Option<&str>,
     ) -> String {
         format!(
             r#"-- This file is automatically @generated by Vemigrate CLI.
 create keyspace if not exists {} with replication = {{ 'class' : '{}', 'replication_factor': {} }};
-create table if not exists {}.migrations (
+{}"#,
+            keyspace,
+            replication_strategy,
+            replication_factor,
+            Self::migrations_table_up(keyspace, table, table_options)
+        )
+    }
+
+    /// Checks that options of the migrations table start with `WITH`.
+    pub fn check_table_options(options: &str) -> Result<()> {
+        let starts_with_keyword = options
+            .split_whitespace()
+            .next()
+            .is_some_and(|word| word.eq_ignore_ascii_case("with"));
+        if !starts_with_keyword {
+            return Err(Error::InvalidTableOptions(options.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Every event is a row clustered by `seq`, so that rolling back doesn't
+    /// overwrite the row of migrating up. Tables created with `primary key(id)`
+    /// are not migrated and keep only the latest event of every migration,
+    /// which tells its net state, older rows without `seq` are read as the
+    /// first events, see `select_history`.
+    fn migrations_table_up(keyspace: &str, table: &str, table_options: Option<&str>) -> String {
+        let table_options = match table_options {
+            Some(options) => format!(" {}", options.trim().trim_end_matches(';')),
+            None => String::new(),
+        };
+        format!(
+            r#"create table if not exists {}.{} (
+    id bigint,
+    up boolean,
+    seq bigint,
+    name text,
+    applied_at timestamp,
+    duration_ms bigint,
+    applied_by text,
+    content text,
+    checksum text,
+    down_checksum text,
+    primary key(id, seq)
+){};"#,
+            keyspace, table, table_options
+        )
+    }
+
+    fn locks_table_up(keyspace: &str) -> String {
+        format!(
+            r#"create table if not exists {}.migration_locks (
+    name text,
+    owner text,
+    locked_at timestamp,
+    primary key(name)
+);"#,
+            keyspace
+        )
+    }
+
+    fn failures_table_up(keyspace: &str) -> String {
+        format!(
+            r#"create table if not exists {}.migration_failures (
     id bigint,
     up boolean,
+    error text,
+    failed_at timestamp,
     primary key(id)
 );"#,
-            keyspace, replication_strategy, replication_factor, keyspace
+            keyspace
         )
     }
 
-    pub fn initial_migration_down(keyspace: &str) -> String {
+    pub fn initial_migration_down(keyspace: &str, table: &str) -> String {
         format!(
             r#"-- This file is automatically @generated by Vemigrate CLI.
-drop table if exists {}.migrations;
+drop table if exists {}.{};
 drop keyspace if exists {};"#,
-            keyspace, keyspace
+            keyspace, table, keyspace
         )
     }
 }
 
-#[derive(Clone, Debug, TryFromRow, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Migration {
     pub id: i64,
     pub up: bool,
+    /// Microseconds since the unix epoch when the event was stored.
+    pub seq: Option<i64>,
+    /// Name of the migration directory, if it was recorded.
+    pub name: Option<String>,
+    /// Milliseconds since the unix epoch when the migration was applied or rolled back.
+    pub applied_at: Option<i64>,
+    /// Milliseconds executing the migration took, if it was recorded.
+    pub duration_ms: Option<i64>,
+    pub applied_by: Option<String>,
+    /// Content of the applied `up` file, if it was recorded.
+    pub content: Option<String>,
+    /// Hex encoded SHA-256 hash of the applied `up` file, if it was recorded.
+    pub checksum: Option<String>,
+    /// Hex encoded SHA-256 hash of the `down` file when the migration was applied.
+    pub down_checksum: Option<String>,
+}
+
+impl Migration {
+    fn from_row(row: Row, optional_columns: &[&str]) -> CDRSResult<Self> {
+        let has = |name| optional_columns.contains(&name);
+        Ok(Migration {
+            id: row.get_r_by_name("id")?,
+            up: row.get_r_by_name("up")?,
+            seq: if has(HISTORY_SEQUENCE_COLUMN) {
+                row.get_by_name(HISTORY_SEQUENCE_COLUMN)?
+            } else {
+                None
+            },
+            name: if has(HISTORY_NAME_COLUMN) {
+                row.get_by_name(HISTORY_NAME_COLUMN)?
+            } else {
+                None
+            },
+            applied_at: if has(HISTORY_APPLIED_AT_COLUMN) {
+                row.get_by_name(HISTORY_APPLIED_AT_COLUMN)?
+            } else {
+                None
+            },
+            duration_ms: if has(HISTORY_DURATION_COLUMN) {
+                row.get_by_name(HISTORY_DURATION_COLUMN)?
+            } else {
+                None
+            },
+            applied_by: if has(HISTORY_APPLIED_BY_COLUMN) {
+                row.get_by_name(HISTORY_APPLIED_BY_COLUMN)?
+            } else {
+                None
+            },
+            content: if has(HISTORY_CONTENT_COLUMN) {
+                row.get_by_name(HISTORY_CONTENT_COLUMN)?
+            } else {
+                None
+            },
+            checksum: if has(HISTORY_CHECKSUM_COLUMN) {
+                row.get_by_name(HISTORY_CHECKSUM_COLUMN)?
+            } else {
+                None
+            },
+            down_checksum: if has(HISTORY_DOWN_CHECKSUM_COLUMN) {
+                row.get_by_name(HISTORY_DOWN_CHECKSUM_COLUMN)?
+            } else {
+                None
+            },
+        })
+    }
+}
+
+/// Row of `system_schema.columns`.
+#[derive(Clone, Debug, TryFromRow, PartialEq)]
+pub struct ColumnDefinition {
+    pub table_name: String,
+    pub column_name: String,
+    /// One of `partition_key`, `clustering`, `static` or `regular`.
+    pub kind: String,
+    /// Position in the partition or clustering key, `-1` for other columns.
+    pub position: i32,
+    /// `asc` or `desc` for clustering columns, `none` for other columns.
+    pub clustering_order: String,
+    pub column_type: String,
+}
+
+/// Rebuilds `create table` statements from column definitions,
+/// tables are sorted by name.
+pub fn tables_ddl(keyspace: &str, columns: &[ColumnDefinition]) -> String {
+    let mut tables: BTreeMap<&str, Vec<&ColumnDefinition>> = BTreeMap::new();
+    for column in columns {
+        tables.entry(&column.table_name).or_default().push(column);
+    }
+
+    let statements: Vec<String> = tables
+        .into_iter()
+        .map(|(table, mut columns)| {
+            // Keys first in their positions, then the rest by name like `describe` does
+            columns.sort_by(|a, b| {
+                (kind_order(&a.kind), a.position, &a.column_name).cmp(&(
+                    kind_order(&b.kind),
+                    b.position,
+                    &b.column_name,
+                ))
+            });
+            let keys_of = |kind: &str| -> Vec<&str> {
+                columns
+                    .iter()
+                    .filter(|c| c.kind == kind)
+                    .map(|c| c.column_name.as_str())
+                    .collect()
+            };
+            let partition_key = keys_of("partition_key");
+            let clustering_key = keys_of("clustering");
+
+            let mut definitions: Vec<String> = columns
+                .iter()
+                .map(|c| {
+                    if c.kind == "static" {
+                        format!("    {} {} static", c.column_name, c.column_type)
+                    } else {
+                        format!("    {} {}", c.column_name, c.column_type)
+                    }
+                })
+                .collect();
+            let mut primary_key = if partition_key.len() == 1 {
+                partition_key[0].to_string()
+            } else {
+                format!("({})", partition_key.join(", "))
+            };
+            for column in &clustering_key {
+                primary_key.push_str(", ");
+                primary_key.push_str(column);
+            }
+            definitions.push(format!("    primary key ({})", primary_key));
+
+            let mut statement = format!(
+                "create table {}.{} (\n{}\n)",
+                keyspace,
+                table,
+                definitions.join(",\n")
+            );
+            // Ascending order is the default one
+            if columns
+                .iter()
+                .any(|c| c.kind == "clustering" && c.clustering_order == "desc")
+            {
+                let orders: Vec<String> = columns
+                    .iter()
+                    .filter(|c| c.kind == "clustering")
+                    .map(|c| format!("{} {}", c.column_name, c.clustering_order))
+                    .collect();
+                statement.push_str(&format!(
+                    " with clustering order by ({})",
+                    orders.join(", ")
+                ));
+            }
+            statement.push(';');
+            statement
+        })
+        .collect();
+    statements.join("\n\n")
+}
+
+/// Converts the time to a CQL `timestamp`, milliseconds since the unix epoch.
+fn timestamp_millis(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .expect("get unix timestamp")
+        .as_millis() as i64
+}
+
+/// Whether the server rejected the query because its keyspace does not exist,
+/// which is an invalid query for both Cassandra and Scylla.
+fn is_missing_keyspace(err: &cdrs::Error) -> bool {
+    is_invalid_query(err, &["keyspace", "does not exist"])
+}
+
+/// Whether the server rejected the query because its table does not exist,
+/// reported as `unconfigured table` by Scylla and older Cassandra versions,
+/// and as `table ... does not exist` by Cassandra 4.
+fn is_missing_table(err: &cdrs::Error) -> bool {
+    is_invalid_query(err, &["unconfigured table"])
+        || is_invalid_query(err, &["table", "does not exist"])
+}
+
+/// Whether the server rejected the query as invalid with a message
+/// containing all the given words, ignoring case.
+fn is_invalid_query(err: &cdrs::Error, words: &[&str]) -> bool {
+    match err {
+        cdrs::Error::Server(err) => {
+            let message = err.message.as_str().to_lowercase();
+            matches!(err.additional_info, AdditionalErrorInfo::Invalid(_))
+                && words.iter().all(|word| message.contains(word))
+        }
+        _ => false,
+    }
+}
+
+/// Checks whether the statement can be a part of a batch,
+/// only `INSERT`, `UPDATE` and `DELETE` statements can.
+fn is_batch_statement(q: &str) -> bool {
+    let keyword = q.split_whitespace().next().unwrap_or_default();
+    ["insert", "update", "delete"]
+        .iter()
+        .any(|k| keyword.eq_ignore_ascii_case(k))
+}
+
+/// Checks whether the queries can be executed in a logged batch with the
+/// history insert: a few small `INSERT`, `UPDATE` and `DELETE` statements
+/// without conditions, which can't span tables in a batch, and without
+/// updates like `c = c + 1`, which may be counter updates. Statements of a
+/// batch share a timestamp, so the order of writes to the same row would be
+/// lost, each table must be written by a single statement.
+fn is_batchable(queries: &[String]) -> bool {
+    if queries.is_empty()
+        || queries.len() > BATCH_MAX_STATEMENTS
+        || queries.iter().map(String::len).sum::<usize>() > BATCH_MAX_BYTES
+    {
+        return false;
+    }
+    let mut tables = HashSet::new();
+    queries.iter().all(|q| {
+        let tokens: Vec<&str> = cql_tokens(q).into_iter().map(|(_, t)| t).collect();
+        let is_conditional = tokens.iter().any(|t| t.eq_ignore_ascii_case("if"));
+        let is_increment = tokens.windows(4).any(|w| {
+            w[1] == "=" && w[0].eq_ignore_ascii_case(w[2]) && (w[3] == "+" || w[3] == "-")
+        });
+        let is_first_write = written_table(&tokens).is_some_and(|table| tables.insert(table));
+        is_batch_statement(q) && !is_conditional && !is_increment && is_first_write
+    })
+}
+
+/// Returns the table written by an `INSERT`, `UPDATE` or `DELETE` statement,
+/// lowercased unless quoted, with its keyspace if qualified.
+fn written_table(tokens: &[&str]) -> Option<String> {
+    let keyword = tokens.first()?;
+    let before_name = if keyword.eq_ignore_ascii_case("insert") {
+        tokens.iter().position(|t| t.eq_ignore_ascii_case("into"))?
+    } else if keyword.eq_ignore_ascii_case("delete") {
+        tokens.iter().position(|t| t.eq_ignore_ascii_case("from"))?
+    } else {
+        0
+    };
+    let mut name = String::new();
+    let mut parts = tokens[before_name + 1..].iter();
+    while let Some(part) = parts.next() {
+        if part.starts_with('"') {
+            name.push_str(part);
+        } else {
+            name.push_str(&part.to_lowercase());
+        }
+        match parts.next() {
+            Some(&".") => name.push('.'),
+            _ => break,
+        }
+    }
+    Some(name).filter(|name| !name.is_empty())
+}
+
+/// Replaces the keyspace `from` in qualified names like `from.table` and in
+/// the keyspace position of statements like `CREATE KEYSPACE from` or `USE from`,
+/// ignoring case like CQL does. Quoted identifiers and string literals are kept.
+fn replace_keyspace(q: &str, from: &str, to: &str) -> String {
+    let tokens = cql_tokens(q);
+    let is_keyspace = |i: usize| {
+        if tokens.get(i + 1).is_some_and(|&(_, next)| next == ".") {
+            return true;
+        }
+        // Skip `IF [NOT] EXISTS` between the keyword and the name
+        let mut j = i;
+        while j > 0
+            && ["if", "not", "exists"]
+                .iter()
+                .any(|w| tokens[j - 1].1.eq_ignore_ascii_case(w))
+        {
+            j -= 1;
+        }
+        j > 0
+            && ["keyspace", "use"]
+                .iter()
+                .any(|w| tokens[j - 1].1.eq_ignore_ascii_case(w))
+    };
+
+    let mut res = String::with_capacity(q.len());
+    let mut copied = 0;
+    for (i, &(start, token)) in tokens.iter().enumerate() {
+        if token.eq_ignore_ascii_case(from) && is_keyspace(i) {
+            res.push_str(&q[copied..start]);
+            res.push_str(to);
+            copied = start + token.len();
+        }
+    }
+    res.push_str(&q[copied..]);
+    res
+}
+
+/// Splits the query into tokens with their offsets: unquoted words, quoted
+/// identifiers and string literals with their quotes, and single other
+/// characters. Whitespace is skipped.
+fn cql_tokens(q: &str) -> Vec<(usize, &str)> {
+    let is_word_char = |c: char| c.is_alphanumeric() || c == '_';
+    let mut tokens = Vec::new();
+    let mut chars = q.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let end = match c {
+            '\'' | '"' => {
+                // A doubled quote is an escaped one
+                let mut end = q.len();
+                while let Some((i, next)) = chars.next() {
+                    if next == c {
+                        if chars.peek().is_some_and(|&(_, after)| after == c) {
+                            chars.next();
+                            continue;
+                        }
+                        end = i + 1;
+                        break;
+                    }
+                }
+                end
+            }
+            '$' if q[start..].starts_with("$$") => match q[start + 2..].find("$$") {
+                Some(i) => {
+                    let end = start + 2 + i + 2;
+                    while chars.peek().is_some_and(|&(i, _)| i < end) {
+                        chars.next();
+                    }
+                    end
+                }
+                None => {
+                    chars.by_ref().for_each(drop);
+                    q.len()
+                }
+            },
+            c if c.is_whitespace() => continue,
+            c if is_word_char(c) => {
+                let mut end = start + c.len_utf8();
+                while let Some(&(i, next)) = chars.peek() {
+                    if !is_word_char(next) {
+                        break;
+                    }
+                    end = i + next.len_utf8();
+                    chars.next();
+                }
+                end
+            }
+            c => start + c.len_utf8(),
+        };
+        tokens.push((start, &q[start..end]));
+    }
+    tokens
+}
+
+fn kind_order(kind: &str) -> u8 {
+    match kind {
+        "partition_key" => 0,
+        "clustering" => 1,
+        _ => 2,
+    }
 }
 
 impl MigrationRow for Migration {
@@ -139,6 +1410,40 @@ impl MigrationRow for Migration {
     fn is_up(&self) -> bool {
         self.up
     }
+
+    fn sequence(&self) -> Option<u64> {
+        self.seq.map(|seq| seq as u64)
+    }
+
+    fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    fn applied_at(&self) -> Option<SystemTime> {
+        self.applied_at
+            .map(|millis| UNIX_EPOCH + Duration::from_millis(millis as u64))
+    }
+
+    fn duration(&self) -> Option<Duration> {
+        self.duration_ms
+            .map(|millis| Duration::from_millis(millis as u64))
+    }
+
+    fn applied_by(&self) -> Option<&str> {
+        self.applied_by.as_deref()
+    }
+
+    fn content(&self) -> Option<&str> {
+        self.content.as_deref()
+    }
+
+    fn checksum(&self) -> Option<&str> {
+        self.checksum.as_deref()
+    }
+
+    fn down_checksum(&self) -> Option<&str> {
+        self.down_checksum.as_deref()
+    }
 }
 
 impl<'a> Store for ScyllaStore<'a> {
@@ -148,82 +1453,219 @@ impl<'a> Store for ScyllaStore<'a> {
     fn get_all(&self) -> Result<Option<Vec<Self::Row>>> {
         debug!("select migrations history");
 
-        let rows = self
-            .conn
-            .query_with_values_tw(
-                "select * from system_schema.keyspaces where keyspace_name = ?;",
-                query_values!(self.keyspace),
-                false,
-                false,
-            )?
-            .get_body()?
-            .into_rows();
-
-        match rows {
-            Some(rows) => {
-                if rows.is_empty() {
-                    debug!("keyspace doe not exist");
+        // Schema changes made by the initial migration may take a moment to propagate
+        let mut retries = if self.bootstrapped.load(Ordering::Relaxed) {
+            BOOTSTRAP_RETRIES
+        } else {
+            0
+        };
+        loop {
+            let res = self.keyspace_exists().and_then(|exists| {
+                if !exists {
                     return Ok(None);
                 }
+                // Adopting an existing keyspace, which has no history yet,
+                // the table is created by `ensure_schema` before migrating
+                if retries == 0 && self.history_columns()?.is_empty() {
+                    return Ok(Some(None));
+                }
+                self.select_history().map(Some)
+            });
+            match res {
+                Ok(Some(rows)) => {
+                    self.bootstrapped.store(false, Ordering::Relaxed);
+                    return Ok(rows);
+                }
+                Ok(None) | Err(_) if retries > 0 => {
+                    debug!("migrations table is not visible yet, retrying");
+                    retries -= 1;
+                    thread::sleep(BOOTSTRAP_RETRY_DELAY);
+                }
+                Ok(None) => {
+                    debug!("keyspace does not exist");
+                    return Ok(None);
+                }
+                Err(err) => return Err(err),
             }
-            None => {
-                debug!("keyspace doe not exist");
-                return Ok(None);
+        }
+    }
+
+    fn add(&self, id: u64, up: bool) -> Result<()> {
+        self.record(&HistoryEvent {
+            id,
+            up,
+            name: None,
+            applied_at: None,
+            duration: None,
+            applied_by: None,
+            content: None,
+            checksum: None,
+            down_checksum: None,
+        })
+    }
+
+    fn record(&self, event: &HistoryEvent) -> Result<()> {
+        debug!(
+            "store migration with id = {} and up = {}",
+            event.id, event.up
+        );
+        self.hold_lock()?;
+        let (insert, values) = self.history_insert(event)?;
+        self.query_history(insert, values)?;
+        self.clear_failure(event.id)
+    }
+
+    /// Executes queries of the migration and the history insert in a single
+    /// logged batch if the migration is small and the queries are allowed
+    /// in it, see `is_batchable`. Others are executed before the insert.
+    fn exec_migration(&self, queries: &[String], event: &HistoryEvent) -> Result<()> {
+        if !is_batchable(queries) {
+            let started_at = Instant::now();
+            for query in queries {
+                self.exec(query)?;
             }
-        };
+            return self.record(&HistoryEvent {
+                duration: Some(started_at.elapsed()),
+                ..event.clone()
+            });
+        }
+
+        debug!(
+            "store migration with id = {} and up = {} in a batch",
+            event.id, event.up
+        );
+        let (insert, values) = self.history_insert(event)?;
+        let mut batch = String::from("begin batch\n");
+        for query in queries {
+            // Statements parsed with a custom delimiter have no terminator
+            let query = self.rewrite_query(query);
+            batch.push_str(query.trim_end().trim_end_matches(';'));
+            batch.push_str(";\n");
+        }
+        batch.push_str(&insert);
+        batch.push_str("\napply batch;");
+        self.hold_lock()?;
+        debug!("exec query: {}", batch);
+        self.query_history(batch, values)?;
+        self.clear_failure(event.id)
+    }
 
-        let res = self
+    fn record_failure(&self, id: u64, up: bool, error: &str) -> Result<()> {
+        debug!(
+            "store failure of migration with id = {} and up = {}",
+            id, up
+        );
+        // The keyspace may have been created by the run, after `ensure_schema`
+        if !self.failures_table.swap(true, Ordering::Relaxed) {
+            self.conn
+                .query_tw(Self::failures_table_up(self.keyspace), false, false)?;
+        }
+        let params = QueryParamsBuilder::new()
+            .consistency(self.history_consistency)
+            .values(query_values!(id as i64, up, error))
+            .finalize();
+        self.conn
+            .query_with_params(
+                format!(
+                    "insert into {}.migration_failures (id, up, error, failed_at) \
+                     values (?, ?, ?, toTimestamp(now()));",
+                    self.keyspace
+                ),
+                params,
+            )
+            .map_err(Error::from)
+            .map(|_| ())
+    }
+
+    fn get_failures(&self) -> Result<Vec<u64>> {
+        if !self.table_exists("migration_failures")? {
+            return Ok(Vec::new());
+        }
+        let rows = self
             .conn
             .query_tw(
-                format!("select id, up from {}.migrations", self.keyspace),
+                format!("select id from {}.migration_failures", self.keyspace),
                 false,
                 false,
             )?
             .get_body()?
-            .into_rows();
-
-        match res {
-            Some(rows) => {
-                if rows.is_empty() {
-                    debug!("no migrations found in history");
-                    return Ok(None);
-                }
+            .into_rows()
+            .unwrap_or_default();
+        Ok(rows
+            .into_iter()
+            .map(|row| row.get_r_by_name("id").map(|id: i64| id as u64))
+            .collect::<CDRSResult<Vec<u64>>>()?)
+    }
 
-                Ok(Some(
-                    rows.into_iter()
-                        .map(Self::Row::try_from_row)
-                        .collect::<CDRSResult<Vec<Self::Row>>>()
-                        .map_err(Error::from)?,
-                ))
-            }
-            None => {
-                debug!("no migrations found in history");
-                Ok(None)
-            }
+    /// Inserts the lock row with a lightweight transaction, it expires after
+    /// `LOCK_TTL` unless refreshed by recording migrations. A keyspace which
+    /// doesn't exist yet is locked once the initial migration creates it, before
+    /// its history is recorded, see `hold_lock`.
+    fn acquire_lock(&self) -> Result<bool> {
+        if !self.keyspace_exists()? {
+            debug!("keyspace does not exist, lock after the initial migration");
+            self.lock_deferred.store(true, Ordering::Relaxed);
+            return Ok(true);
         }
+        self.insert_lock()
     }
 
-    fn add(&self, id: u64, up: bool) -> Result<()> {
-        debug!("store migration with id = {} and up = {}", id, up);
+    fn release_lock(&self) -> Result<()> {
+        self.lock_deferred.store(false, Ordering::Relaxed);
+        if !self.locked.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        debug!("release lock {}", LOCK_NAME);
+        let params = QueryParamsBuilder::new()
+            .consistency(self.history_consistency)
+            .values(query_values!(LOCK_NAME, self.lock_owner.as_str()))
+            .finalize();
         self.conn
-            .query_with_values_tw(
+            .query_with_params(
                 format!(
-                    "insert into {}.migrations (id,up) values (?, ?);",
+                    "delete from {}.migration_locks where name = ? if owner = ?;",
                     self.keyspace
                 ),
-                query_values!(id, up),
-                false,
-                false,
+                params,
             )
             .map_err(Error::from)
             .map(|_| ())
     }
 
+    /// Creates the migrations table in an existing keyspace adopted by vemigrate,
+    /// or adds optional columns missing in a table created by an older version.
+    /// The failures table is created too.
+    /// A keyspace which doesn't exist yet is left to the initial migration.
+    fn ensure_schema(&self) -> Result<()> {
+        if !self.keyspace_exists()? {
+            return Ok(());
+        }
+        self.conn
+            .query_tw(Self::failures_table_up(self.keyspace), false, false)?;
+        self.failures_table.store(true, Ordering::Relaxed);
+        let columns = self.history_columns()?;
+        if columns.is_empty() {
+            info!(
+                "creating table {} in keyspace {}",
+                self.table, self.keyspace
+            );
+            return self.exec(&Self::migrations_table_up(self.keyspace, self.table, None));
+        }
+        for ddl in self.missing_columns_ddl(&columns) {
+            info!("upgrade the migrations table: {}", ddl);
+            self.conn.query_tw(ddl, false, false)?;
+        }
+        Ok(())
+    }
+
     fn exec(&self, q: &str) -> Result<()> {
+        let rewritten = self.rewrite_query(q);
+        let q = rewritten.as_str();
         debug!("exec query: {}", q);
-        self.conn
-            .query_tw(q, false, false)
-            .map_err(Error::from)
-            .map(|_| ())
+        self.conn.query_tw(q, false, false).map_err(Error::from)?;
+        if self.is_bootstrap_query(q) {
+            self.bootstrapped.store(true, Ordering::Relaxed);
+        }
+        Ok(())
     }
 }
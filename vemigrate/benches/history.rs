@@ -0,0 +1,97 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use vemigrate::{MigrationRow, Migrator, Store};
+
+use std::fmt;
+use std::path::Path;
+
+const HISTORY_LEN: u64 = 100_000;
+
+#[derive(Clone)]
+struct Row {
+    id: u64,
+    up: bool,
+    seq: Option<u64>,
+}
+
+impl MigrationRow for Row {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn is_up(&self) -> bool {
+        self.up
+    }
+
+    fn sequence(&self) -> Option<u64> {
+        self.seq
+    }
+}
+
+#[derive(Debug)]
+struct NoError;
+
+impl fmt::Display for NoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("no error")
+    }
+}
+
+impl std::error::Error for NoError {}
+
+/// Store returning a prepared history, every 10th migration was rolled back.
+struct History(Vec<Row>);
+
+impl History {
+    fn new(sequenced: bool) -> Self {
+        let mut rows = Vec::new();
+        for id in 0..HISTORY_LEN {
+            rows.push(Row {
+                id,
+                up: true,
+                seq: None,
+            });
+            if id % 10 == 0 {
+                rows.push(Row {
+                    id,
+                    up: false,
+                    seq: None,
+                });
+            }
+        }
+        if sequenced {
+            for (seq, row) in rows.iter_mut().enumerate() {
+                row.seq = Some(seq as u64);
+            }
+        }
+        History(rows)
+    }
+}
+
+impl Store for History {
+    type Row = Row;
+    type Error = NoError;
+
+    fn get_all(&self) -> Result<Option<Vec<Row>>, NoError> {
+        Ok(Some(self.0.clone()))
+    }
+
+    fn add(&self, _id: u64, _up: bool) -> Result<(), NoError> {
+        Ok(())
+    }
+
+    fn exec(&self, _q: &str) -> Result<(), NoError> {
+        Ok(())
+    }
+}
+
+fn net_state(c: &mut Criterion) {
+    for (name, sequenced) in [("net_state", false), ("net_state_sequenced", true)] {
+        let migrator = Migrator::with_store(Path::new("migrations"), History::new(sequenced));
+        c.bench_function(name, |b| {
+            b.iter(|| black_box(migrator.net_state().unwrap()))
+        });
+    }
+}
+
+criterion_group!(benches, net_state);
+criterion_main!(benches);
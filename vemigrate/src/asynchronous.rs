@@ -0,0 +1,171 @@
+use crate::{
+    check_reversible, compare_checksums, duplicate_ids, history_counters, CqlFile, Error,
+    HistoryEvent, MigrationRow, Migrator, Result,
+};
+
+use std::future::Future;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+/// Asynchronous counterpart of `Store` for services migrating from async code,
+/// used by `Migrator::migrate_up_async` and `Migrator::migrate_down_async`.
+pub trait AsyncStore {
+    type Row: MigrationRow;
+    type Error: std::error::Error + 'static;
+
+    fn get_all(
+        &self,
+    ) -> impl Future<Output = std::result::Result<Option<Vec<Self::Row>>, Self::Error>>;
+    fn add(&self, id: u64, up: bool) -> impl Future<Output = std::result::Result<(), Self::Error>>;
+    fn exec(&self, q: &str) -> impl Future<Output = std::result::Result<(), Self::Error>>;
+
+    /// Stores the event with all its details, see `Store::record`.
+    fn record(
+        &self,
+        event: &HistoryEvent,
+    ) -> impl Future<Output = std::result::Result<(), Self::Error>> {
+        self.add(event.id, event.up)
+    }
+
+    /// Stores the error of a failed migration, see `Store::record_failure`.
+    fn record_failure(
+        &self,
+        _id: u64,
+        _up: bool,
+        _error: &str,
+    ) -> impl Future<Output = std::result::Result<(), Self::Error>> {
+        async { Ok(()) }
+    }
+
+    /// Prepares or upgrades the history kept by the store, see `Store::ensure_schema`.
+    fn ensure_schema(&self) -> impl Future<Output = std::result::Result<(), Self::Error>> {
+        async { Ok(()) }
+    }
+}
+
+/// Migration files are still read synchronously, they are small and read once per run.
+///
+/// Duplicates, checksums, `require_initialized` and `max_version` are checked
+/// like by the synchronous methods, the history is upgraded with `ensure_schema`
+/// and failures are recorded. Timeouts, parallel statements, the rate limit,
+/// `only_failed`, `recheck_history`, preflight hooks, audit sinks and the lock
+/// of the store apply to the synchronous methods only, and migrators created
+/// with `with_slice` are not supported.
+impl<'a, S> Migrator<'a, S>
+where
+    S: AsyncStore,
+{
+    /// Migrates up all pending migrations,
+    /// returns the id of the last one or None if database is already up to date.
+    pub async fn migrate_up_async(&self) -> Result<Option<u64>> {
+        self.migrate_n_async(true, None).await
+    }
+
+    /// Migrates down all applied migrations like `migrate_down`,
+    /// returns the id of the last one or None if there is nothing to roll back.
+    pub async fn migrate_down_async(&self) -> Result<Option<u64>> {
+        self.migrate_n_async(false, None).await
+    }
+
+    /// Migrates up `n` times or less, returns the same id as `migrate_up_n`.
+    pub async fn migrate_up_n_async(&self, n: usize) -> Result<Option<u64>> {
+        self.migrate_n_async(true, Some(n)).await
+    }
+
+    /// Migrates down `n` times or less, returns the same id as `migrate_down_n`.
+    pub async fn migrate_down_n_async(&self, n: usize) -> Result<Option<u64>> {
+        self.migrate_n_async(false, Some(n)).await
+    }
+
+    async fn migrate_n_async(&self, up: bool, n: Option<usize>) -> Result<Option<u64>> {
+        if !self.schema_ensured.load(Ordering::Relaxed) {
+            self.store
+                .ensure_schema()
+                .await
+                .map_err(|err| Error::Store(Box::new(err)))?;
+            self.schema_ensured.store(true, Ordering::Relaxed);
+        }
+        let migrations = self.discover()?;
+        if up && !self.allow_duplicates {
+            let ids = duplicate_ids(&migrations);
+            if !ids.is_empty() {
+                return Err(Error::Duplicates(ids));
+            }
+        }
+        let rows = self
+            .store
+            .get_all()
+            .await
+            .map_err(|err| Error::Store(Box::new(err)))?;
+        if up && self.require_initialized && rows.is_none() {
+            return Err(Error::NotInitialized);
+        }
+        let history = history_counters(rows);
+        if up && self.verify_checksums {
+            let rows = self
+                .store
+                .get_all()
+                .await
+                .map_err(|err| Error::Store(Box::new(err)))?
+                .unwrap_or_default();
+            compare_checksums(&migrations, &history, rows)?;
+        }
+        let mut pending = match self.filter_migrations(migrations, history, up)? {
+            Some(pending) => pending,
+            None => return Ok(None),
+        };
+        if let Some(max) = self.max_version.filter(|_| up) {
+            pending.retain(|(id, _)| *id <= max);
+            if pending.is_empty() {
+                return Ok(None);
+            }
+        }
+
+        let take_n = n.map_or(pending.len(), |n| n.min(pending.len()));
+        // The next pending migration if some are left, like `execute`
+        let last_id = match pending.get(take_n) {
+            Some((id, _)) => *id,
+            None => pending[pending.len() - 1].0,
+        };
+        if !up {
+            // Fail before rolling back anything
+            check_reversible(&pending[..take_n])?;
+        }
+        let add_history = up || take_n != pending.len();
+        for (id, cql) in pending.into_iter().take(take_n) {
+            if let Err(err) = self.exec_one_async(id, cql, up, add_history).await {
+                let recorded = self.store.record_failure(id, up, &err.to_string()).await;
+                if let Err(store_err) = recorded {
+                    log::warn!("failed to record the failure of {}: {}", id, store_err);
+                }
+                return Err(err);
+            }
+        }
+        Ok(Some(last_id))
+    }
+
+    async fn exec_one_async(
+        &self,
+        id: u64,
+        cql: CqlFile,
+        up: bool,
+        add_history: bool,
+    ) -> Result<()> {
+        let started_at = Instant::now();
+        for query in &cql.queries {
+            self.store
+                .exec(query)
+                .await
+                .map_err(|err| Error::Store(Box::new(err)))?;
+        }
+        if add_history {
+            let mut event = self.history_event(id, up)?;
+            event.duration = Some(started_at.elapsed());
+            self.store
+                .record(&event)
+                .await
+                .map_err(|err| Error::Store(Box::new(err)))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,24 @@
+use std::time::{Duration, SystemTime};
+
+/// Execution of a single migration passed to audit sinks,
+/// see `Migrator::audit_sink`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuditRecord {
+    pub id: u64,
+    pub up: bool,
+    /// Count of statements of the migration.
+    pub statements: usize,
+    pub duration: Duration,
+    /// Error the migration failed with, None if it succeeded.
+    pub error: Option<String>,
+    /// When the migration started.
+    pub started_at: SystemTime,
+    /// Who runs migrations, see `Migrator::applied_by`.
+    pub applied_by: Option<String>,
+}
+
+impl AuditRecord {
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+}
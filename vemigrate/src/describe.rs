@@ -0,0 +1,123 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{read_cql_file, read_down_file, Migrator, Result, Store};
+
+/// Version of the `PlanDescription` format, increased on incompatible changes.
+pub const DESCRIPTION_FORMAT_VERSION: u32 = 1;
+
+/// Machine readable description of all migrations, see `Migrator::describe`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PlanDescription {
+    pub format_version: u32,
+    /// Migrations in the order of execution.
+    pub migrations: Vec<MigrationDescription>,
+}
+
+impl PlanDescription {
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MigrationDescription {
+    pub id: u64,
+    /// Full directory name, including the timestamp.
+    pub name: String,
+    pub status: MigrationStatus,
+    /// Queries of the `up` file.
+    pub up: Vec<String>,
+    /// Queries of the `down` file.
+    pub down: Vec<String>,
+    /// Hex encoded SHA-256 hash of both files, see `MigrationDir::checksum`.
+    pub checksum: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MigrationStatus {
+    Applied,
+    Pending,
+    /// Skipped by the `.disabled` marker or the `Disabled` directive.
+    Disabled,
+    /// Applied according to the history, but the `up` file is missing.
+    MissingFile,
+}
+
+/// Status of a single migration, see `Migrator::status`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MigrationState {
+    pub id: u64,
+    /// Full directory name, None if the directory is missing.
+    pub name: Option<String>,
+    pub status: MigrationStatus,
+}
+
+impl<'a, S> Migrator<'a, S>
+where
+    S: Store,
+{
+    /// Describes status, queries and checksums of all migrations.
+    pub fn describe(&self) -> Result<PlanDescription> {
+        let history = self.get_migration_history()?;
+        let mut migrations = Vec::new();
+        for m in self.discover()? {
+            let up = read_cql_file(&m.file(true), &self.parse_options)?;
+            let down = read_down_file(&m, &self.parse_options)?;
+            let status = if history.get(&m.id) == Some(&1) {
+                MigrationStatus::Applied
+            } else if m.disabled || up.is_disabled() {
+                MigrationStatus::Disabled
+            } else {
+                MigrationStatus::Pending
+            };
+            migrations.push(MigrationDescription {
+                id: m.id,
+                checksum: m.checksum()?,
+                name: m.name,
+                status,
+                up: up.queries,
+                down: down.queries,
+            });
+        }
+        Ok(PlanDescription {
+            format_version: DESCRIPTION_FORMAT_VERSION,
+            migrations,
+        })
+    }
+
+    /// Returns statuses of migrations found in the directory or the history
+    /// sorted by id, without executing anything.
+    pub fn status(&self) -> Result<Vec<MigrationState>> {
+        let history = self.get_migration_history()?;
+        let mut res = Vec::new();
+        for m in self.discover()? {
+            let up_path = m.file(true);
+            let status = if !up_path.exists() {
+                MigrationStatus::MissingFile
+            } else if history.get(&m.id) == Some(&1) {
+                MigrationStatus::Applied
+            } else if m.disabled || read_cql_file(&up_path, &self.parse_options)?.is_disabled() {
+                MigrationStatus::Disabled
+            } else {
+                MigrationStatus::Pending
+            };
+            res.push(MigrationState {
+                id: m.id,
+                name: Some(m.name),
+                status,
+            });
+        }
+        for (id, counter) in history {
+            if counter == 1 && !res.iter().any(|state| state.id == id) {
+                res.push(MigrationState {
+                    id,
+                    name: None,
+                    status: MigrationStatus::MissingFile,
+                });
+            }
+        }
+        res.sort_by_key(|state| state.id);
+        Ok(res)
+    }
+}
@@ -0,0 +1,283 @@
+use crate::{parse_cql, ParseOptions, Result};
+
+const GENERATED_HEADER: &str =
+    "-- This file is @generated by `vemigrate diff`, review it before applying.";
+
+/// Table found in a `CREATE TABLE` statement of a schema snapshot.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SchemaTable {
+    pub name: String,
+    pub columns: Vec<SchemaColumn>,
+    /// Original `CREATE TABLE` statement.
+    pub statement: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SchemaColumn {
+    pub name: String,
+    pub kind: String,
+}
+
+/// Coarse difference between two schema snapshots, only tables and their
+/// columns are compared; keys, options and other objects are ignored.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SchemaDiff {
+    pub added_tables: Vec<SchemaTable>,
+    pub removed_tables: Vec<SchemaTable>,
+    /// Added columns with names of their tables.
+    pub added_columns: Vec<(String, SchemaColumn)>,
+    /// Removed columns with names of their tables.
+    pub removed_columns: Vec<(String, SchemaColumn)>,
+}
+
+impl SchemaDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added_tables.is_empty()
+            && self.removed_tables.is_empty()
+            && self.added_columns.is_empty()
+            && self.removed_columns.is_empty()
+    }
+
+    /// Returns CQL which turns the `from` schema into the `to` schema.
+    pub fn up_cql(&self) -> String {
+        let mut res = vec![GENERATED_HEADER.to_string()];
+        res.extend(self.added_tables.iter().map(create_table));
+        res.extend(self.added_columns.iter().map(|(t, c)| add_column(t, c)));
+        res.extend(self.removed_columns.iter().map(|(t, c)| drop_column(t, c)));
+        res.extend(self.removed_tables.iter().map(drop_table));
+        res.join("\n")
+    }
+
+    /// Returns CQL which turns the `to` schema back into the `from` schema.
+    pub fn down_cql(&self) -> String {
+        let mut res = vec![GENERATED_HEADER.to_string()];
+        res.extend(self.removed_tables.iter().map(create_table));
+        res.extend(self.removed_columns.iter().map(|(t, c)| add_column(t, c)));
+        res.extend(self.added_columns.iter().map(|(t, c)| drop_column(t, c)));
+        res.extend(self.added_tables.iter().map(drop_table));
+        res.join("\n")
+    }
+}
+
+/// Compares tables and columns created by two schema snapshots.
+pub fn diff_schemas(from: &str, to: &str) -> Result<SchemaDiff> {
+    let from_tables = parse_tables(from)?;
+    let to_tables = parse_tables(to)?;
+
+    let mut res = SchemaDiff::default();
+    for table in &to_tables {
+        match find_table(&from_tables, &table.name) {
+            Some(old) => {
+                for column in &table.columns {
+                    if find_column(old, &column.name).is_none() {
+                        res.added_columns.push((table.name.clone(), column.clone()));
+                    }
+                }
+                for column in &old.columns {
+                    if find_column(table, &column.name).is_none() {
+                        res.removed_columns
+                            .push((table.name.clone(), column.clone()));
+                    }
+                }
+            }
+            None => res.added_tables.push(table.clone()),
+        }
+    }
+    for table in &from_tables {
+        if find_table(&to_tables, &table.name).is_none() {
+            res.removed_tables.push(table.clone());
+        }
+    }
+    Ok(res)
+}
+
+fn find_table<'a>(tables: &'a [SchemaTable], name: &str) -> Option<&'a SchemaTable> {
+    let name = normalize_identifier(name);
+    tables
+        .iter()
+        .find(|t| normalize_identifier(&t.name) == name)
+}
+
+fn find_column<'a>(table: &'a SchemaTable, name: &str) -> Option<&'a SchemaColumn> {
+    let name = normalize_identifier(name);
+    table
+        .columns
+        .iter()
+        .find(|c| normalize_identifier(&c.name) == name)
+}
+
+/// Returns the name as CQL resolves it: unquoted parts of a possibly qualified
+/// name are case-insensitive and lowercased, quoted ones like `"UserId"` are
+/// case-sensitive and kept without quotes.
+fn normalize_identifier(name: &str) -> String {
+    let mut res = String::with_capacity(name.len());
+    let mut chars = name.chars().peekable();
+    let mut is_quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if is_quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                res.push('"');
+            }
+            '"' => is_quoted = !is_quoted,
+            c if is_quoted => res.push(c),
+            c => res.push(c.to_ascii_lowercase()),
+        }
+    }
+    res
+}
+
+fn parse_tables(schema: &str) -> Result<Vec<SchemaTable>> {
+    let cql = parse_cql(schema.as_bytes(), &ParseOptions::default())?;
+    Ok(cql.queries.iter().filter_map(|q| parse_table(q)).collect())
+}
+
+/// Parses `CREATE TABLE [IF NOT EXISTS] name (...)`,
+/// returns None for any other statement.
+fn parse_table(statement: &str) -> Option<SchemaTable> {
+    let open = statement.find('(')?;
+    let mut words = statement[..open].split_whitespace();
+    if !words.next()?.eq_ignore_ascii_case("create") || !words.next()?.eq_ignore_ascii_case("table")
+    {
+        return None;
+    }
+    let name = words.last()?.to_string();
+
+    let body = &statement[open + 1..];
+    let close = matching_paren(body)?;
+    let columns = split_top_level(&body[..close])
+        .into_iter()
+        .filter_map(|definition| {
+            let mut parts = definition.trim().splitn(2, char::is_whitespace);
+            let name = parts.next()?.to_string();
+            let kind = parts.next()?.trim();
+            if name.eq_ignore_ascii_case("primary") {
+                // Table level `PRIMARY KEY (...)` definition
+                return None;
+            }
+            Some(SchemaColumn {
+                name,
+                kind: strip_primary_key(kind).to_string(),
+            })
+        })
+        .collect();
+
+    Some(SchemaTable {
+        name,
+        columns,
+        statement: statement.to_string(),
+    })
+}
+
+/// Returns position of the parenthesis closing an already opened one.
+fn matching_paren(val: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in val.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return Some(i),
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Splits by commas which are not inside of `<>` or `()`.
+fn split_top_level(val: &str) -> Vec<&str> {
+    let mut res = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in val.char_indices() {
+        match c {
+            '(' | '<' => depth += 1,
+            ')' | '>' => depth -= 1,
+            ',' if depth == 0 => {
+                res.push(&val[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    res.push(&val[start..]);
+    res
+}
+
+/// Removes inline `PRIMARY KEY` from a column type.
+fn strip_primary_key(kind: &str) -> &str {
+    let lowercase = kind.to_ascii_lowercase();
+    match lowercase.rfind("primary key") {
+        Some(pos) if lowercase[pos..].trim() == "primary key" => kind[..pos].trim_end(),
+        _ => kind,
+    }
+}
+
+fn create_table(table: &SchemaTable) -> String {
+    let statement = table.statement.trim_end_matches(';');
+    format!("{};", statement)
+}
+
+fn drop_table(table: &SchemaTable) -> String {
+    format!("drop table if exists {};", table.name)
+}
+
+fn add_column(table: &str, column: &SchemaColumn) -> String {
+    format!("alter table {} add {} {};", table, column.name, column.kind)
+}
+
+fn drop_column(table: &str, column: &SchemaColumn) -> String {
+    format!("alter table {} drop {};", table, column.name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn added_table() {
+        let from = "CREATE TABLE ks.users (id uuid PRIMARY KEY, name text);";
+        let to = "CREATE TABLE ks.users (id uuid PRIMARY KEY, name text);\n\
+                  CREATE TABLE ks.posts (id uuid, author uuid, PRIMARY KEY (id));";
+        let diff = diff_schemas(from, to).unwrap();
+
+        assert_eq!(diff.added_tables.len(), 1);
+        assert_eq!(diff.added_tables[0].name, "ks.posts");
+        assert_eq!(
+            diff.added_tables[0].columns,
+            vec![
+                SchemaColumn {
+                    name: "id".to_string(),
+                    kind: "uuid".to_string(),
+                },
+                SchemaColumn {
+                    name: "author".to_string(),
+                    kind: "uuid".to_string(),
+                },
+            ]
+        );
+        assert!(diff.removed_tables.is_empty());
+        assert!(diff.added_columns.is_empty());
+        assert!(diff.removed_columns.is_empty());
+        assert!(diff
+            .up_cql()
+            .ends_with("CREATE TABLE ks.posts (id uuid, author uuid, PRIMARY KEY (id));"));
+        assert!(diff.down_cql().ends_with("drop table if exists ks.posts;"));
+    }
+
+    #[test]
+    fn quoted_names_are_case_sensitive() {
+        let from = "CREATE TABLE t (\"UserId\" int PRIMARY KEY, Name text);";
+        let to = "CREATE TABLE T (\"userid\" int PRIMARY KEY, name text);";
+        let diff = diff_schemas(from, to).unwrap();
+
+        assert!(diff.added_tables.is_empty());
+        assert_eq!(diff.added_columns.len(), 1);
+        assert_eq!(diff.added_columns[0].1.name, "\"userid\"");
+        assert_eq!(diff.removed_columns.len(), 1);
+        assert_eq!(diff.removed_columns[0].1.name, "\"UserId\"");
+        assert_eq!(
+            normalize_identifier("\"userid\""),
+            normalize_identifier("UserId")
+        );
+    }
+}
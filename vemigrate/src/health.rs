@@ -0,0 +1,75 @@
+use crate::{Error, Migrator, Result, Store};
+
+/// Summary of the migrator state for health checks, see `Migrator::health`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Health {
+    /// Whether the store answered, all other fields are empty otherwise.
+    pub reachable: bool,
+    /// Whether the store has a history, i.e. the initial migration was applied.
+    pub initialized: bool,
+    /// Count of pending migrations, see `Migrator::count_pending`.
+    pub pending: usize,
+    /// Count of migrations with an inconsistent history, see `Migrator::check_consistency`.
+    pub inconsistent: usize,
+}
+
+impl<'a, S> Migrator<'a, S>
+where
+    S: Store,
+{
+    /// Checks that the store answers by reading the history.
+    pub fn ping(&self) -> Result<()> {
+        self.is_initialized().map(|_| ())
+    }
+
+    /// Checks whether the store has a history.
+    pub fn is_initialized(&self) -> Result<bool> {
+        self.store
+            .get_all()
+            .map(|rows| rows.is_some())
+            .map_err(|err| Error::Store(Box::new(err)))
+    }
+
+    /// Returns the highest id of applied migrations, or None if none is applied.
+    pub fn current_version(&self) -> Result<Option<u64>> {
+        Ok(self
+            .get_migration_history()?
+            .into_iter()
+            .filter(|(_, counter)| *counter == 1)
+            .map(|(id, _)| id)
+            .max())
+    }
+
+    /// Returns ids of migrations whose net state is neither applied nor
+    /// rolled back, see `net_state`.
+    pub fn check_consistency(&self) -> Result<Vec<u64>> {
+        let mut ids: Vec<u64> = self
+            .get_migration_history()?
+            .into_iter()
+            .filter(|(_, counter)| *counter != 0 && *counter != 1)
+            .map(|(id, _)| id)
+            .collect();
+        ids.sort_unstable();
+        Ok(ids)
+    }
+
+    /// Returns a summary of the state in one call, e.g. for a `/healthz` handler.
+    /// Errors of the store are reported as unreachable instead of failing.
+    pub fn health(&self) -> Result<Health> {
+        let health = self.is_initialized().and_then(|initialized| {
+            Ok(Health {
+                reachable: true,
+                initialized,
+                pending: self.count_pending()?,
+                inconsistent: self.check_consistency()?.len(),
+            })
+        });
+        match health {
+            Err(Error::Store(err)) => {
+                log::warn!("store is unreachable: {}", err);
+                Ok(Health::default())
+            }
+            res => res,
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    check_reversible, parse_cql_file, read_down_file, Error, MigrationRow, Migrator, Result, Store,
+};
+
+use std::collections::{BTreeSet, HashMap};
+use std::time::{Duration, SystemTime};
+
+/// Migration history event in a store-independent format.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEvent {
+    pub id: u64,
+    pub up: bool,
+    /// Name of the migration directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub applied_at: Option<SystemTime>,
+    /// How long executing the migration took. It's unknown to stores executing
+    /// the migration in the same request as storing the event, e.g. in a batch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<Duration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub applied_by: Option<String>,
+    /// Content of the `up` file as it was applied, see `Migrator::store_content`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    /// Checksum of the `up` file as it was applied, see `MigrationDir::up_checksum`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+    /// Checksum of the `down` file when the migration was applied,
+    /// see `MigrationDir::down_checksum`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub down_checksum: Option<String>,
+}
+
+/// Set of applied migrations captured by `Migrator::checkpoint`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub applied: BTreeSet<u64>,
+}
+
+impl<'a, S> Migrator<'a, S>
+where
+    S: Store,
+{
+    /// Exports all history events from the store as a JSON array,
+    /// the result can be loaded into another store with `import_history`.
+    pub fn export_history(&self) -> Result<String> {
+        let events: Vec<HistoryEvent> = self
+            .store
+            .get_all()
+            .map_err(|err| Error::Store(Box::new(err)))?
+            .unwrap_or_default()
+            .iter()
+            .map(|row| HistoryEvent {
+                id: row.id(),
+                up: row.is_up(),
+                name: row.name().map(String::from),
+                applied_at: row.applied_at(),
+                duration: row.duration(),
+                applied_by: row.applied_by().map(String::from),
+                content: row.content().map(String::from),
+                checksum: row.checksum().map(String::from),
+                down_checksum: row.down_checksum().map(String::from),
+            })
+            .collect();
+        Ok(serde_json::to_string_pretty(&events)?)
+    }
+
+    /// Replays history events exported by `export_history` into the store.
+    pub fn import_history(&self, json: &str) -> Result<()> {
+        let events: Vec<HistoryEvent> = serde_json::from_str(json)?;
+        self.ensure_schema()?;
+        for event in events {
+            self.store
+                .record(&event)
+                .map_err(|err| Error::Store(Box::new(err)))?;
+        }
+        Ok(())
+    }
+
+    /// Returns up to `top` applied migrations which took the longest to apply,
+    /// the slowest first. Only migrations with a recorded duration are included.
+    pub fn slowest_applied(&self, top: usize) -> Result<Vec<(u64, Duration)>> {
+        let mut rows = self
+            .store
+            .get_all()
+            .map_err(|err| Error::Store(Box::new(err)))?
+            .unwrap_or_default();
+        if rows.iter().all(|row| row.sequence().is_some()) {
+            rows.sort_by_key(|row| row.sequence());
+        }
+        let history = self.get_migration_history()?;
+        // The latest application wins
+        let mut durations = HashMap::new();
+        for row in rows.iter().filter(|row| row.is_up()) {
+            match row.duration() {
+                Some(duration) => durations.insert(row.id(), duration),
+                None => durations.remove(&row.id()),
+            };
+        }
+        let mut slowest: Vec<(u64, Duration)> = durations
+            .into_iter()
+            .filter(|(id, _)| history.get(id) == Some(&1))
+            .collect();
+        slowest.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        slowest.truncate(top);
+        Ok(slowest)
+    }
+
+    /// Captures ids of currently applied migrations.
+    pub fn checkpoint(&self) -> Result<Checkpoint> {
+        let applied = self
+            .get_migration_history()?
+            .into_iter()
+            .filter(|(_, counter)| *counter == 1)
+            .map(|(id, _)| id)
+            .collect();
+        Ok(Checkpoint { applied })
+    }
+
+    /// Migrates to the exact set of migrations captured by `checkpoint`,
+    /// migrations applied since then are rolled back starting from the latest,
+    /// then migrations rolled back since then are applied again.
+    /// Fails before changing anything if any of the rollbacks is irreversible.
+    pub fn restore(&self, checkpoint: &Checkpoint) -> Result<()> {
+        self.locked(|| {
+            let migrations = self.discover()?;
+            let history = self.get_migration_history()?;
+            let is_applied = |id: u64| history.get(&id) == Some(&1);
+
+            let downs = migrations
+                .iter()
+                .rev()
+                .filter(|m| is_applied(m.id) && !checkpoint.applied.contains(&m.id))
+                .map(|m| Ok((m.id, read_down_file(m, &self.parse_options)?)))
+                .collect::<Result<Vec<_>>>()?;
+            check_reversible(&downs)?;
+
+            // Rolling back everything drops the history as well, see `execute_migrations`
+            let add_history = !checkpoint.applied.is_empty();
+            for (id, cql) in downs {
+                self.migrate_one(id, cql, false, add_history)?;
+            }
+            for m in &migrations {
+                if !is_applied(m.id) && checkpoint.applied.contains(&m.id) {
+                    let cql = parse_cql_file(&m.file(true), &self.parse_options)?;
+                    self.migrate_one(m.id, cql, true, true)?;
+                }
+            }
+            Ok(())
+        })
+    }
+}
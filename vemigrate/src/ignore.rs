@@ -0,0 +1,68 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const COMMENT_MARKER: char = '#';
+
+/// Folder names and globs listed in the `.vemigrateignore` file,
+/// one per line, `#` starts a comment line.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct IgnoreList {
+    patterns: Vec<String>,
+}
+
+impl IgnoreList {
+    /// Reads the ignore file, a missing file ignores nothing.
+    pub(crate) fn read(path: &Path) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(Self::parse(&content)),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub(crate) fn parse(content: &str) -> Self {
+        let patterns = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with(COMMENT_MARKER))
+            .map(|line| line.trim_end_matches('/').to_string())
+            .collect();
+        IgnoreList { patterns }
+    }
+
+    pub(crate) fn is_ignored(&self, name: &str) -> bool {
+        self.patterns.iter().any(|p| glob_match(p, name))
+    }
+}
+
+/// Matches the name against a glob where `*` is any sequence of characters
+/// and `?` is any single character.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` and the name position it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, start)) => {
+                    p = star + 1;
+                    n = start + 1;
+                    backtrack = Some((star, start + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
@@ -1,27 +1,119 @@
 #![allow(clippy::type_complexity)]
 
+use sha2::{Digest, Sha256};
+
 use std::borrow::Cow;
-use std::collections::hash_map::Entry;
 use std::collections::HashMap;
-use std::fs::{File, ReadDir};
-use std::io::{BufRead, BufReader, Write};
+use std::fs::File;
+use std::io::{BufReader, Write};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
-use std::time::{SystemTime, UNIX_EPOCH};
-use std::{error, fmt, fs, io};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{error, fmt, fs, io, panic, thread};
+
+#[cfg(feature = "async")]
+mod asynchronous;
+mod audit;
+mod describe;
+mod diff;
+mod health;
+mod history;
+mod ignore;
+mod outcome;
+mod parser;
+
+#[cfg(feature = "async")]
+pub use asynchronous::AsyncStore;
+pub use audit::AuditRecord;
+pub use describe::{
+    MigrationDescription, MigrationState, MigrationStatus, PlanDescription,
+    DESCRIPTION_FORMAT_VERSION,
+};
+pub use diff::{diff_schemas, SchemaColumn, SchemaDiff, SchemaTable};
+pub use health::Health;
+pub use history::{Checkpoint, HistoryEvent};
+pub use outcome::{MigrationOutcome, Timings};
+pub use parser::{is_destructive, parse_cql, CqlFile, Directive, ParseOptions, StatementReader};
+
+use ignore::IgnoreList;
+use parser::{DIRECTIVE_INITIAL, DIRECTIVE_IRREVERSIBLE, DIRECTIVE_PARALLEL};
 
 pub const MIGRATION_FILE_UP: &str = "up.cql";
 pub const MIGRATION_FILE_DOWN: &str = "down.cql";
-
-const COMMENT_LENGTH: usize = 2;
-const COMMENT_LINE_TYPE_1: &str = "--";
-const COMMENT_LINE_TYPE_2: &str = "//";
-const QUERIES_SEPARATOR: char = ';';
+/// Used when the `.cql` file is missing, see `migration_file`.
+pub const MIGRATION_FILE_UP_SQL: &str = "up.sql";
+pub const MIGRATION_FILE_DOWN_SQL: &str = "down.sql";
+/// Name of the migration which creates the keyspace, it always runs first.
+pub const INITIAL_MIGRATION_NAME: &str = "initial";
+/// File which disables the migration it's placed in.
+pub const MIGRATION_DISABLED_MARKER: &str = ".disabled";
+/// File in the migrations directory listing names or globs of migrations to skip.
+pub const MIGRATIONS_IGNORE_FILE: &str = ".vemigrateignore";
 
 #[derive(Debug)]
 pub enum Error {
     ParseMigrationFile(String),
     Store(Box<dyn error::Error>),
     Io(io::Error),
+    Json(serde_json::Error),
+    Timeout {
+        id: u64,
+        timeout: Duration,
+    },
+    Preflight(String),
+    UnknownMigration(u64),
+    /// Migrating up without a history, see `Migrator::require_initialized`.
+    NotInitialized,
+    /// Another migrator holds the lock of the store, see `Store::acquire_lock`.
+    Locked,
+    /// Rolling back a migration without a `down` file.
+    Irreversible {
+        id: u64,
+    },
+    /// Migration file without anything but whitespace.
+    EmptyMigration(PathBuf),
+    Migration {
+        id: u64,
+        up: bool,
+        source: Box<Error>,
+    },
+    Dependency {
+        id: u64,
+        depends_on: u64,
+    },
+    NotNewer {
+        ids: Vec<u64>,
+        baseline: u64,
+    },
+    Duplicates(Vec<u64>),
+    ChecksumMismatch {
+        id: u64,
+        up: bool,
+        expected: String,
+        actual: String,
+    },
+    Rollback {
+        id: u64,
+        source: Box<Error>,
+        rollback: Box<Error>,
+    },
+    StatementsOutOfRange {
+        id: u64,
+        range: Range<usize>,
+        len: usize,
+    },
+}
+
+impl Error {
+    /// Returns the error of the store if it is of the given type,
+    /// e.g. to retry only on timeouts of a specific store.
+    pub fn store_error<E: error::Error + 'static>(&self) -> Option<&E> {
+        match *self {
+            Error::Store(ref e) => e.downcast_ref::<E>(),
+            _ => None,
+        }
+    }
 }
 
 impl error::Error for Error {}
@@ -32,6 +124,72 @@ impl fmt::Display for Error {
             Error::ParseMigrationFile(ref err) => f.write_str(err),
             Error::Store(ref e) => e.fmt(f),
             Error::Io(ref e) => e.fmt(f),
+            Error::Json(ref e) => e.fmt(f),
+            Error::Preflight(ref err) => write!(f, "preflight check failed: {}", err),
+            Error::Timeout { id, timeout } => {
+                write!(f, "migration {} did not complete within {:?}", id, timeout)
+            }
+            Error::UnknownMigration(id) => write!(f, "migration {} not found", id),
+            Error::NotInitialized => write!(
+                f,
+                "the history doesn't exist yet, apply the initial migration separately first"
+            ),
+            Error::Locked => write!(
+                f,
+                "another migrator is running against the database, try again once it's done"
+            ),
+            Error::Irreversible { id } => {
+                write!(f, "migration {} is irreversible, it has no down file", id)
+            }
+            Error::EmptyMigration(ref path) => {
+                write!(f, "migration file {} is empty", path.display())
+            }
+            Error::Migration { id, up, ref source } => {
+                let direction = if up { "up" } else { "down" };
+                write!(f, "migration {} failed {}: {}", id, direction, source)
+            }
+            Error::Dependency { id, depends_on } => write!(
+                f,
+                "migration {} depends on {}, which is neither applied nor migrated before it",
+                id, depends_on
+            ),
+            Error::NotNewer { ref ids, baseline } => write!(
+                f,
+                "migrations {:?} are not newer than {} and were never applied",
+                ids, baseline
+            ),
+            Error::Duplicates(ref ids) => write!(
+                f,
+                "timestamps {:?} are used by more than one migration",
+                ids
+            ),
+            Error::ChecksumMismatch {
+                id,
+                up,
+                ref expected,
+                ref actual,
+            } => write!(
+                f,
+                "{} file of applied migration {} was changed, its checksum {} was {} when applied",
+                if up { "up" } else { "down" },
+                id,
+                actual,
+                expected
+            ),
+            Error::Rollback {
+                id,
+                ref source,
+                ref rollback,
+            } => write!(
+                f,
+                "{}, then rolling back migration {} failed: {}",
+                source, id, rollback
+            ),
+            Error::StatementsOutOfRange { id, ref range, len } => write!(
+                f,
+                "statements {:?} are out of range, migration {} has {} statements",
+                range, id, len
+            ),
         }
     }
 }
@@ -42,11 +200,59 @@ impl From<io::Error> for Error {
     }
 }
 
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Json(err)
+    }
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 pub trait MigrationRow {
     fn id(&self) -> u64;
     fn is_up(&self) -> bool;
+
+    /// Value increasing with every stored event, which orders events
+    /// of stores not returning them in the order they were added.
+    fn sequence(&self) -> Option<u64> {
+        None
+    }
+
+    /// Name of the migration directory, if the store records it.
+    fn name(&self) -> Option<&str> {
+        None
+    }
+
+    /// When the event was stored, if the store records it.
+    fn applied_at(&self) -> Option<SystemTime> {
+        None
+    }
+
+    /// How long executing the migration took, if the store records it.
+    fn duration(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Who executed the migration, if the store records it.
+    fn applied_by(&self) -> Option<&str> {
+        None
+    }
+
+    /// Content of the applied `up` file, if the store records it.
+    fn content(&self) -> Option<&str> {
+        None
+    }
+
+    /// Hex encoded SHA-256 hash of the applied `up` file, if the store records it.
+    fn checksum(&self) -> Option<&str> {
+        None
+    }
+
+    /// Hex encoded SHA-256 hash of the `down` file when the migration was applied,
+    /// if the store records it.
+    fn down_checksum(&self) -> Option<&str> {
+        None
+    }
 }
 
 pub trait Store {
@@ -56,6 +262,75 @@ pub trait Store {
     fn get_all(&self) -> std::result::Result<Option<Vec<Self::Row>>, Self::Error>;
     fn add(&self, id: u64, up: bool) -> std::result::Result<(), Self::Error>;
     fn exec(&self, q: &str) -> std::result::Result<(), Self::Error>;
+
+    /// Stores the event with all its details,
+    /// stores which keep only the id and the direction may rely on `add`.
+    fn record(&self, event: &HistoryEvent) -> std::result::Result<(), Self::Error> {
+        self.add(event.id, event.up)
+    }
+
+    /// Executes the query which should complete before the deadline,
+    /// stores able to interrupt a running query should override it.
+    fn exec_until(&self, q: &str, _deadline: Instant) -> std::result::Result<(), Self::Error> {
+        self.exec(q)
+    }
+
+    /// Executes queries of the migration and stores the event. Stores able to
+    /// do both at once, so that a crash can't leave an applied migration
+    /// unrecorded, should override it.
+    ///
+    /// It's not used for migrations with a timeout, parallel statements,
+    /// or when the rate is limited.
+    fn exec_migration(
+        &self,
+        queries: &[String],
+        event: &HistoryEvent,
+    ) -> std::result::Result<(), Self::Error> {
+        let started_at = Instant::now();
+        for query in queries {
+            self.exec(query)?;
+        }
+        self.record(&HistoryEvent {
+            duration: Some(started_at.elapsed()),
+            ..event.clone()
+        })
+    }
+
+    /// Stores that the migration failed with the error, so that it can be
+    /// retried with `Migrator::only_failed`. Stores may ignore failures.
+    fn record_failure(
+        &self,
+        _id: u64,
+        _up: bool,
+        _error: &str,
+    ) -> std::result::Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Returns ids of migrations stored by `record_failure`.
+    fn get_failures(&self) -> std::result::Result<Vec<u64>, Self::Error> {
+        Ok(Vec::new())
+    }
+
+    /// Takes the lock shared by all migrators of the database, so that only
+    /// one of them migrates at a time. Returns false if another one holds it.
+    /// Stores without locks always succeed.
+    fn acquire_lock(&self) -> std::result::Result<bool, Self::Error> {
+        Ok(true)
+    }
+
+    /// Releases the lock taken by `acquire_lock`.
+    fn release_lock(&self) -> std::result::Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Prepares or upgrades the history kept by the store, e.g. adds columns
+    /// of new features. It's called once per migrator before it migrates,
+    /// holding the lock, or writes the history otherwise, and must be idempotent.
+    /// Reads of the history must not rely on it, nor change the database.
+    fn ensure_schema(&self) -> std::result::Result<(), Self::Error> {
+        Ok(())
+    }
 }
 
 pub fn create_migration<P, Q>(
@@ -68,13 +343,7 @@ where
     P: AsRef<Path>,
     Q: AsRef<[u8]>,
 {
-    let unix_timestamp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .expect("get unix timestamp");
-    let migration_path =
-        migrations_dir
-            .as_ref()
-            .join(format!("{}_{}", unix_timestamp.as_secs(), name));
+    let migration_path = migration_path(name, migrations_dir);
     fs::create_dir_all(&migration_path)?;
     create_migration_file(migration_path.join(MIGRATION_FILE_UP), Some(q_up.as_ref()))?;
     create_migration_file(
@@ -84,6 +353,53 @@ where
     Ok(migration_path)
 }
 
+/// Creates a migration with the `up` file only, which can't be rolled back.
+pub fn create_irreversible_migration<P, Q>(
+    name: &str,
+    migrations_dir: P,
+    q_up: Q,
+) -> std::io::Result<PathBuf>
+where
+    P: AsRef<Path>,
+    Q: AsRef<[u8]>,
+{
+    let migration_path = migration_path(name, migrations_dir);
+    fs::create_dir_all(&migration_path)?;
+    create_migration_file(migration_path.join(MIGRATION_FILE_UP), Some(q_up.as_ref()))?;
+    Ok(migration_path)
+}
+
+/// Returns path of a new migration with the given name and the current
+/// timestamp in milliseconds as the version, which is increased if another
+/// migration already has it. Migrations with timestamps in seconds still
+/// sort before new ones.
+pub fn migration_path<P: AsRef<Path>>(name: &str, migrations_dir: P) -> PathBuf {
+    let mut timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("get unix timestamp")
+        .as_millis() as u64;
+    let used: Vec<u64> = fs::read_dir(&migrations_dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| {
+            entry
+                .ok()?
+                .file_name()
+                .to_str()?
+                .split('_')
+                .next()?
+                .parse()
+                .ok()
+        })
+        .collect();
+    while used.contains(&timestamp) {
+        timestamp += 1;
+    }
+    migrations_dir
+        .as_ref()
+        .join(format!("{}_{}", timestamp, name))
+}
+
 fn create_migration_file(path: PathBuf, q: Option<&[u8]>) -> std::io::Result<()> {
     let mut f = fs::File::create(path)?;
     if let Some(bytes) = q {
@@ -93,15 +409,343 @@ fn create_migration_file(path: PathBuf, q: Option<&[u8]>) -> std::io::Result<()>
     Ok(())
 }
 
+/// Migration directory named as `{timestamp}_{name}`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MigrationDir {
+    /// Timestamp prefix of the directory name.
+    pub id: u64,
+    /// Full directory name.
+    pub name: String,
+    pub path: PathBuf,
+    /// Whether the directory contains the `.disabled` marker file.
+    pub disabled: bool,
+    /// Whether it's the initial migration, named `initial` or marked with
+    /// `-- +migrate Initial`, which is sorted first regardless of the timestamp.
+    pub initial: bool,
+    /// Names of the `up` and `down` files if they are not the default ones,
+    /// see `Migrator::file_names`.
+    pub file_names: Option<(String, String)>,
+}
+
+impl MigrationDir {
+    /// Returns the name given to `create_migration`, which is not unique,
+    /// use the full `name` to tell migrations apart.
+    pub fn short_name(&self) -> &str {
+        self.name
+            .split_once('_')
+            .map(|(_, name)| name)
+            .unwrap_or_default()
+    }
+
+    /// Returns path to the `up` or `down` file of this migration.
+    pub fn file(&self, up: bool) -> PathBuf {
+        named_file(&self.path, up, &self.file_names)
+    }
+
+    /// Returns a hex encoded SHA-256 hash of the `up` and `down` files.
+    pub fn checksum(&self) -> Result<String> {
+        let mut hasher = Sha256::new();
+        self.hash_files(&mut hasher)?;
+        Ok(to_hex(hasher))
+    }
+
+    /// Returns a hex encoded SHA-256 hash of the `up` file,
+    /// which is recorded in the history when it's applied.
+    pub fn up_checksum(&self) -> Result<String> {
+        Ok(checksum(&fs::read(self.file(true))?))
+    }
+
+    /// Returns a hex encoded SHA-256 hash of the `down` file, a missing one
+    /// is hashed as empty, see `up_checksum`.
+    pub fn down_checksum(&self) -> Result<String> {
+        match fs::read(self.file(false)) {
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(checksum(&[])),
+            res => Ok(checksum(&res?)),
+        }
+    }
+
+    fn hash_files(&self, hasher: &mut Sha256) -> io::Result<()> {
+        for up in &[true, false] {
+            let content = match fs::read(self.file(*up)) {
+                // Irreversible migrations have no `down` file
+                Err(ref err) if !*up && err.kind() == io::ErrorKind::NotFound => Vec::new(),
+                res => res?,
+            };
+            hasher.update((content.len() as u64).to_be_bytes());
+            hasher.update(&content);
+        }
+        Ok(())
+    }
+}
+
+fn checksum(content: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(content);
+    to_hex(hasher)
+}
+
+fn to_hex(hasher: Sha256) -> String {
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Lists migration directories sorted by timestamp, the initial migration first,
+/// directories without a numeric timestamp prefix are skipped,
+/// with a warning if the name looks like a migration.
+/// Directories listed in the `.vemigrateignore` file are skipped as well.
+/// Disabled migrations are listed as well.
+pub fn discover<P: AsRef<Path>>(migrations_dir: P) -> Result<Vec<MigrationDir>> {
+    let migrations_dir = migrations_dir.as_ref();
+    let ignore = IgnoreList::read(&migrations_dir.join(MIGRATIONS_IGNORE_FILE))?;
+    let mut res: Vec<MigrationDir> = Vec::new();
+    for entry in fs::read_dir(migrations_dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        // Follows symlinks, so a dangling one is reported instead of skipped
+        let metadata = fs::metadata(&path)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))?;
+        if !metadata.is_dir() {
+            continue;
+        }
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(name) => {
+                log::warn!("skipping {:?}: name is not valid UTF-8", name);
+                continue;
+            }
+        };
+        if ignore.is_ignored(&name) {
+            continue;
+        }
+        let timestamp_prefix = name.split('_').next().unwrap_or_default();
+        match timestamp_prefix.parse::<u64>() {
+            Ok(id) => res.push(MigrationDir {
+                id,
+                disabled: path.join(MIGRATION_DISABLED_MARKER).exists(),
+                initial: is_initial(&name, &migration_file(&path, true)),
+                name,
+                path,
+                file_names: None,
+            }),
+            Err(_) => {
+                if name.contains('_') {
+                    log::warn!(
+                        "skipping '{}': name doesn't start with a numeric timestamp",
+                        name
+                    );
+                }
+            }
+        }
+    }
+    res.sort_by_key(|m| (!m.initial, m.id));
+    Ok(res)
+}
+
+/// Returns path to the `up` or `down` file in the migration directory, which is
+/// the `.sql` file if only that one exists, e.g. when files are shared with
+/// a SQL database project. The `.cql` file is preferred.
+pub fn migration_file(dir: &Path, up: bool) -> PathBuf {
+    let (cql, sql) = if up {
+        (MIGRATION_FILE_UP, MIGRATION_FILE_UP_SQL)
+    } else {
+        (MIGRATION_FILE_DOWN, MIGRATION_FILE_DOWN_SQL)
+    };
+    let path = dir.join(cql);
+    if !path.exists() && dir.join(sql).exists() {
+        return dir.join(sql);
+    }
+    path
+}
+
+/// Returns path to the `up` or `down` file with custom names, if any.
+fn named_file(dir: &Path, up: bool, names: &Option<(String, String)>) -> PathBuf {
+    match *names {
+        Some((ref up_name, _)) if up => dir.join(up_name),
+        Some((_, ref down_name)) => dir.join(down_name),
+        None => migration_file(dir, up),
+    }
+}
+
+/// Checks the name of the migration and the `Initial` directive of its `up` file,
+/// errors of the file are left to be reported when it's executed.
+fn is_initial(name: &str, up_path: &Path) -> bool {
+    let mut parts = name.splitn(2, '_');
+    if parts.nth(1) == Some(INITIAL_MIGRATION_NAME) {
+        return true;
+    }
+    read_cql_file(up_path, &ParseOptions::default())
+        .map(|cql| cql.directive(DIRECTIVE_INITIAL).is_some())
+        .unwrap_or(false)
+}
+
+/// Parses both `up` and `down` files of every migration,
+/// returns the first error found.
+pub fn validate<P: AsRef<Path>>(migrations_dir: P) -> Result<()> {
+    validate_with(migrations_dir, &ParseOptions::default())
+}
+
+/// Same as `validate`, but doesn't stop at the first broken migration,
+/// returns all broken migrations with their errors instead.
+pub fn validate_all<P: AsRef<Path>>(migrations_dir: P) -> Result<Vec<(MigrationDir, Error)>> {
+    validate_all_with(migrations_dir, &ParseOptions::default())
+}
+
+/// Returns ids of migrations whose timestamp is not strictly greater than
+/// timestamps of all migrations listed before them in name order,
+/// such as duplicated timestamps or ones with a different precision.
+pub fn check_monotonic<P: AsRef<Path>>(migrations_dir: P) -> Result<Vec<u64>> {
+    let mut migrations = discover(migrations_dir)?;
+    migrations.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut res = Vec::new();
+    let mut max_id: Option<u64> = None;
+    for migration in migrations {
+        match max_id {
+            Some(max) if migration.id <= max => res.push(migration.id),
+            _ => max_id = Some(migration.id),
+        }
+    }
+    Ok(res)
+}
+
+/// Returns timestamps used by more than one migration, e.g. after a merge,
+/// the history can't tell such migrations apart.
+pub fn check_duplicates<P: AsRef<Path>>(migrations_dir: P) -> Result<Vec<u64>> {
+    Ok(duplicate_ids(&discover(migrations_dir)?))
+}
+
+fn duplicate_ids(migrations: &[MigrationDir]) -> Vec<u64> {
+    let mut counts: HashMap<u64, usize> = HashMap::with_capacity(migrations.len());
+    for migration in migrations {
+        *counts.entry(migration.id).or_insert(0) += 1;
+    }
+    let mut res: Vec<u64> = counts
+        .into_iter()
+        .filter(|(_, count)| *count > 1)
+        .map(|(id, _)| id)
+        .collect();
+    res.sort_unstable();
+    res
+}
+
+fn validate_with<P: AsRef<Path>>(migrations_dir: P, options: &ParseOptions) -> Result<()> {
+    for migration in discover(migrations_dir)? {
+        validate_migration(&migration, options)?;
+    }
+    Ok(())
+}
+
+fn validate_all_with<P: AsRef<Path>>(
+    migrations_dir: P,
+    options: &ParseOptions,
+) -> Result<Vec<(MigrationDir, Error)>> {
+    let mut res = Vec::new();
+    for migration in discover(migrations_dir)? {
+        if let Err(err) = validate_migration(&migration, options) {
+            res.push((migration, err));
+        }
+    }
+    Ok(res)
+}
+
+fn validate_migration(migration: &MigrationDir, options: &ParseOptions) -> Result<()> {
+    if migration.disabled {
+        return Ok(());
+    }
+    let up_path = migration.file(true);
+    let up = read_cql_file(&up_path, options)?;
+    if up.is_disabled() {
+        return Ok(());
+    }
+    check_queries(&up_path, up)?;
+    read_down_file(migration, options)?;
+    Ok(())
+}
+
+fn parse_cql_file(path: &Path, options: &ParseOptions) -> Result<CqlFile> {
+    check_queries(path, read_cql_file(path, options)?)
+}
+
+fn read_cql_file(path: &Path, options: &ParseOptions) -> Result<CqlFile> {
+    let file = File::open(path)?;
+    parse_cql(BufReader::new(file), options)
+}
+
+/// Parses the `down` file, a missing or empty one makes the migration irreversible.
+fn read_down_file(migration: &MigrationDir, options: &ParseOptions) -> Result<CqlFile> {
+    let path = migration.file(false);
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err.into()),
+    };
+    if content.trim().is_empty() {
+        return Ok(irreversible_down());
+    }
+    let cql = parse_cql(content.as_bytes(), options)?;
+    if cql.queries.is_empty() && !cql.is_irreversible() {
+        log::info!(
+            "{} has comments only, rolling back is a no-op",
+            path.display()
+        );
+    }
+    Ok(cql)
+}
+
+fn irreversible_down() -> CqlFile {
+    CqlFile {
+        directives: vec![Directive {
+            name: DIRECTIVE_IRREVERSIBLE.to_string(),
+            value: None,
+        }],
+        ..CqlFile::default()
+    }
+}
+
+/// Rejects `up` files without queries, `down` files with comments only are
+/// an intentional no-op rollback, see `read_down_file`.
+fn check_queries(path: &Path, parsed: CqlFile) -> Result<CqlFile> {
+    if !parsed.queries.is_empty() {
+        return Ok(parsed);
+    }
+    if fs::read_to_string(path)?.trim().is_empty() {
+        return Err(Error::EmptyMigration(path.to_path_buf()));
+    }
+    Err(Error::ParseMigrationFile(format!(
+        "no CQL found in {}",
+        path.display()
+    )))
+}
+
+/// Executes queries using up to the given number of threads.
+type ParallelExec<S> = fn(&S, &[String], usize, Option<Instant>) -> Result<()>;
+
 pub struct Migrator<'a, S> {
     path: Cow<'a, Path>,
     store: S,
+    parse_options: ParseOptions,
+    parallel_statements: Option<(usize, ParallelExec<S>)>,
+    preflight_hooks: Vec<Box<dyn Fn(&S) -> Result<()> + 'a>>,
+    audit_sinks: Vec<Box<dyn Fn(&AuditRecord) + 'a>>,
+    recheck_history: bool,
+    rate_limit: Option<u64>,
+    applied_by: Option<String>,
+    tag: Option<String>,
+    allow_duplicates: bool,
+    only_failed: bool,
+    store_content: bool,
+    verify_checksums: bool,
+    require_initialized: bool,
+    max_version: Option<u64>,
+    slice: Option<Vec<(u64, String, String)>>,
+    schema_ensured: AtomicBool,
+    file_names: Option<(String, String)>,
 }
 
-impl<'a, S> Migrator<'a, S>
-where
-    S: Store,
-{
+impl<'a, S> Migrator<'a, S> {
     pub fn with_store<P>(path: P, store: S) -> Self
     where
         P: Into<Cow<'a, Path>>,
@@ -109,193 +753,917 @@ where
         Migrator {
             path: path.into(),
             store,
+            parse_options: ParseOptions::default(),
+            parallel_statements: None,
+            preflight_hooks: Vec::new(),
+            audit_sinks: Vec::new(),
+            recheck_history: false,
+            rate_limit: None,
+            applied_by: None,
+            tag: None,
+            allow_duplicates: false,
+            only_failed: false,
+            store_content: false,
+            verify_checksums: true,
+            require_initialized: false,
+            max_version: None,
+            slice: None,
+            schema_ensured: AtomicBool::new(false),
+            file_names: None,
         }
     }
 
-    #[inline]
-    fn migrate_n(&self, up: bool, n: Option<usize>) -> Result<Option<u64>> {
-        // Try to read migrations dir first
-        let dir = fs::read_dir(&self.path)?;
-
-        let migration_history = self.get_migration_history()?;
-        match self.filter_migrations(dir, migration_history, up)? {
-            Some(migrations_to_execute) => self.execute_migrations(migrations_to_execute, up, n),
-            None => Ok(None),
+    /// Creates a migrator of migrations built at runtime instead of read from
+    /// the migrations directory, given as `(id, up, down)` CQL. They run in
+    /// the order of ids and are recorded in the history like files.
+    ///
+    /// Only migrating up and down with `migrate*` methods is supported,
+    /// methods inspecting the migrations directory fail.
+    pub fn with_slice(store: S, mut migrations: Vec<(u64, String, String)>) -> Self {
+        migrations.sort_by_key(|(id, _, _)| *id);
+        Migrator {
+            slice: Some(migrations),
+            ..Self::with_store(Path::new(""), store)
         }
     }
 
-    /// Migrates up,
-    /// returns None if database is already up to date.
-    pub fn migrate_up(&self) -> Result<Option<u64>> {
-        self.migrate_n(true, None)
+    /// Sets who runs migrations, which is recorded in the history
+    /// by stores supporting it.
+    pub fn applied_by<N: Into<String>>(mut self, name: N) -> Self {
+        self.applied_by = Some(name.into());
+        self
     }
 
-    /// Migrates down,
-    /// returns None if database is already up to date.
-    pub fn migrate_down(&self) -> Result<Option<u64>> {
-        self.migrate_n(false, None)
+    /// Migrates up only migrations having the tag in `-- +migrate Tags` directive
+    /// of the `up` file, other pending migrations are left as is.
+    pub fn only_tag<T: Into<String>>(mut self, tag: T) -> Self {
+        self.tag = Some(tag.into());
+        self
     }
 
-    /// Migrates up `n` times or less,
-    /// returns None if database is already up to date.
-    pub fn migrate_up_n(&self, n: usize) -> Result<Option<u64>> {
-        self.migrate_n(true, Some(n))
+    /// Allows to migrate up when timestamps are used by more than one migration,
+    /// see `check_duplicates`.
+    pub fn allow_duplicates(mut self, enabled: bool) -> Self {
+        self.allow_duplicates = enabled;
+        self
     }
 
-    /// Migrates down `n` times or less,
-    /// returns None if database is already up to date.
-    pub fn migrate_down_n(&self, n: usize) -> Result<Option<u64>> {
-        self.migrate_n(false, Some(n))
+    /// Migrates up only pending migrations whose last run failed,
+    /// see `Store::record_failure`.
+    pub fn only_failed(mut self, enabled: bool) -> Self {
+        self.only_failed = enabled;
+        self
     }
 
-    fn get_migration_history(&self) -> Result<HashMap<u64, isize>> {
-        let res: HashMap<u64, isize> = match self
-            .store
-            .get_all()
-            .map_err(|err| Error::Store(Box::new(err)))?
-        {
-            Some(migrations) => migrations.into_iter().fold(HashMap::new(), |mut acc, m| {
-                let increment = if m.is_up() { 1 } else { -1 };
-                match acc.entry(m.id()) {
-                    Entry::Occupied(o) => {
-                        *o.into_mut() += increment;
-                    }
-                    Entry::Vacant(v) => {
-                        v.insert(increment);
-                    }
-                }
-                acc
-            }),
-            None => HashMap::new(),
-        };
-        Ok(res)
+    /// Records the content of every applied `up` file in the history, so that
+    /// it's known what exactly ran even after the file was changed.
+    /// The history grows by the size of all migrations, it's off by default.
+    pub fn store_content(mut self, enabled: bool) -> Self {
+        self.store_content = enabled;
+        self
     }
 
-    fn parse_cql_file(path: PathBuf) -> Result<Option<Vec<String>>> {
-        let file = File::open(path)?;
+    /// Checks before migrating up that `up` and `down` files of applied migrations
+    /// were not changed since they were applied, enabled by default. Only files
+    /// with a checksum in the history are checked.
+    pub fn verify_checksums(mut self, enabled: bool) -> Self {
+        self.verify_checksums = enabled;
+        self
+    }
 
-        let mut queries = Vec::new();
-        let mut reader = BufReader::new(file);
-        let mut bytes_count: usize;
-        let mut buf = String::new();
-        let mut is_new_query = false;
-        loop {
-            bytes_count = reader.read_line(&mut buf)?;
-            if bytes_count == 0 {
-                break;
-            }
+    /// Reads migrations from files with the given names instead of `up.cql`
+    /// and `down.cql`, e.g. to keep the layout of an existing directory.
+    pub fn file_names<U, D>(mut self, up: U, down: D) -> Self
+    where
+        U: Into<String>,
+        D: Into<String>,
+    {
+        self.file_names = Some((up.into(), down.into()));
+        self
+    }
 
-            let trimmed = buf.trim();
-            if !trimmed.is_empty() && !is_cql_comment_line(trimmed) {
-                if is_new_query {
-                    queries.push(String::new());
-                }
-                if trimmed.chars().last().unwrap() == QUERIES_SEPARATOR {
-                    is_new_query = true
-                } else {
-                    is_new_query = false
-                }
+    /// Fails to migrate up without a history instead of applying the initial
+    /// migration, e.g. so that a keyspace is never created by accident
+    /// in a wrong environment.
+    pub fn require_initialized(mut self, enabled: bool) -> Self {
+        self.require_initialized = enabled;
+        self
+    }
 
-                if queries.is_empty() {
-                    queries.push(trimmed.to_string());
-                } else {
-                    queries.last_mut().unwrap().push_str(trimmed);
-                }
-            }
+    /// Never migrates up past the given migration, later pending migrations are
+    /// skipped as if `migrate_to` was used, e.g. to keep unreleased migrations
+    /// out of production. Rolling back is not affected.
+    pub fn max_version(mut self, version: u64) -> Self {
+        self.max_version = Some(version);
+        self
+    }
 
-            buf.clear();
-        }
+    /// Limits the approximate count of CQL bytes sent per second by sleeping
+    /// after every statement in proportion to its size, e.g. for backfills
+    /// on a shared cluster. Statements executed in parallel are not limited.
+    pub fn rate_limit(mut self, bytes_per_second: u64) -> Self {
+        self.rate_limit = Some(bytes_per_second).filter(|rate| *rate > 0);
+        self
+    }
 
-        if queries.is_empty() {
-            return Ok(None);
-        }
-        Ok(Some(queries))
+    /// Returns the store migrations are executed with.
+    pub fn store(&self) -> &S {
+        &self.store
     }
 
-    fn filter_migrations(
-        &self,
-        dir: ReadDir,
-        history: HashMap<u64, isize>,
-        up: bool,
-    ) -> Result<Option<Vec<(u64, Vec<String>)>>> {
-        let mut res: Vec<(u64, Vec<String>)> = dir
-            .map(|r| r.unwrap())
-            .filter(|elem| elem.metadata().unwrap().is_dir())
-            .filter_map(
-                |elem| match elem.file_name().to_str().unwrap().splitn(2, '_').next() {
-                    Some(timestamp_prefix) => match timestamp_prefix.parse::<u64>() {
-                        Ok(timestamp) => {
-                            let counter = *history.get(&timestamp).unwrap_or(&0);
-                            if up && counter == 0 || (!up && counter == 1) {
-                                let mut up_path = elem.path();
-                                if up {
-                                    up_path.push(MIGRATION_FILE_UP);
-                                } else {
-                                    up_path.push(MIGRATION_FILE_DOWN);
-                                }
-                                Some((timestamp, up_path))
-                            } else {
-                                None
-                            }
-                        }
-                        Err(_) => None,
-                    },
-                    None => None,
-                },
-            )
-            .map(|m| {
-                let queries = match Self::parse_cql_file(m.1.clone())? {
-                    Some(v) => v,
-                    None => {
-                        return Err(Error::ParseMigrationFile(format!(
-                            "no CQL found in {}",
-                            m.1.display()
-                        )))
-                    }
-                };
+    /// Points the migrator to another migrations directory keeping the same store,
+    /// e.g. to migrate directories of several modules with a single session.
+    pub fn with_path<P>(mut self, path: P) -> Self
+    where
+        P: Into<Cow<'a, Path>>,
+    {
+        self.path = path.into();
+        self
+    }
 
-                Ok((m.0, queries))
-            })
-            .collect::<Result<Vec<(u64, Vec<String>)>>>()?;
-        if res.is_empty() {
-            return Ok(None);
-        }
-        if up {
-            res.sort_by(|(a_timestamp, _), (b_timestamp, _)| a_timestamp.cmp(&b_timestamp));
-        } else {
-            res.sort_by(|(a_timestamp, _), (b_timestamp, _)| b_timestamp.cmp(&a_timestamp));
-        }
-        Ok(Some(res))
+    /// Re-reads the history right before executing every migration and skips
+    /// ones which were migrated by another process in the meantime.
+    /// Costs one history read per migration.
+    pub fn recheck_history(mut self, enabled: bool) -> Self {
+        self.recheck_history = enabled;
+        self
     }
 
-    fn migrate_one(
-        &self,
-        timestamp: u64,
-        queries: Vec<String>,
-        up: bool,
-        add_history: bool,
-    ) -> Result<()> {
-        for query in queries {
-            self.store
-                .exec(&query)
-                .map_err(|err| Error::Store(Box::new(err)))?;
-        }
+    /// Adds a check executed against the store before migrating,
+    /// an error returned by the hook aborts the run.
+    pub fn preflight_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&S) -> Result<()> + 'a,
+    {
+        self.preflight_hooks.push(Box::new(hook));
+        self
+    }
 
-        if add_history {
-            return self
-                .store
-                .add(timestamp, up)
-                .map_err(|err| Error::Store(Box::new(err)));
-        }
-        Ok(())
+    /// Adds a sink receiving a record of every executed migration, whether it
+    /// succeeded or failed, e.g. to forward them to an audit log.
+    pub fn audit_sink<F>(mut self, sink: F) -> Self
+    where
+        F: Fn(&AuditRecord) + 'a,
+    {
+        self.audit_sinks.push(Box::new(sink));
+        self
     }
 
-    pub fn execute_migrations(
-        &self,
-        migration_to_execute: Vec<(u64, Vec<String>)>,
+    /// Allows queries of migrations marked with `-- +migrate Parallel` to be
+    /// executed by up to `n` threads, meant for independent data statements.
+    pub fn parallel_statements(mut self, n: usize) -> Self
+    where
+        S: Store + Sync,
+        S::Error: Send,
+    {
+        self.parallel_statements = Some((n, exec_parallel::<S>));
+        self
+    }
+
+    /// Fails parsing of a file if a statement starts before the previous one
+    /// is terminated, the last one may be left unterminated if allowed.
+    pub fn require_terminators(mut self, allow_unterminated_last: bool) -> Self {
+        self.parse_options.require_terminators = true;
+        self.parse_options.allow_unterminated_last = allow_unterminated_last;
+        self
+    }
+
+    /// Sets the terminator of statements in migration files, `;` by default.
+    pub fn statement_delimiter<D: Into<String>>(mut self, delimiter: D) -> Self {
+        self.parse_options.statement_delimiter = delimiter.into();
+        self
+    }
+}
+
+impl<'a, S> Migrator<'a, S>
+where
+    S: Store,
+{
+    #[inline]
+    fn migrate_n(&self, up: bool, n: Option<usize>) -> Result<Option<u64>> {
+        self.migrate_n_from(up, n, None)
+    }
+
+    #[inline]
+    fn migrate_n_from(&self, up: bool, n: Option<usize>, from: Option<u64>) -> Result<Option<u64>> {
+        Ok(self.run(up, n, from)?.map(|outcome| outcome.last_id))
+    }
+
+    /// Migrates up or down `n` times or less, all pending migrations if `n` is None,
+    /// returns the summary of the run or None if database is already up to date.
+    pub fn migrate_outcome(&self, up: bool, n: Option<usize>) -> Result<Option<MigrationOutcome>> {
+        self.run(up, n, None)
+    }
+
+    fn run(
+        &self,
+        up: bool,
+        n: Option<usize>,
+        from: Option<u64>,
+    ) -> Result<Option<MigrationOutcome>> {
+        for hook in &self.preflight_hooks {
+            hook(&self.store)?;
+        }
+        self.locked(|| {
+            if up && self.require_initialized && !self.is_initialized()? {
+                return Err(Error::NotInitialized);
+            }
+
+            let (migrations_to_execute, migration_history) = self.pending(up, from)?;
+            match migrations_to_execute {
+                Some(migrations_to_execute) => {
+                    let outcome = self.execute(migrations_to_execute, up, n)?;
+                    Ok(Some(outcome.with_versions(&migration_history)))
+                }
+                None => Ok(None),
+            }
+        })
+    }
+
+    /// Upgrades the history of the store once, see `Store::ensure_schema`.
+    fn ensure_schema(&self) -> Result<()> {
+        if self.schema_ensured.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        self.store
+            .ensure_schema()
+            .map_err(|err| Error::Store(Box::new(err)))?;
+        self.schema_ensured.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Runs `f` holding the lock of the store, which is released however `f` ends.
+    fn locked<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce() -> Result<T>,
+    {
+        let acquired = self
+            .store
+            .acquire_lock()
+            .map_err(|err| Error::Store(Box::new(err)))?;
+        if !acquired {
+            return Err(Error::Locked);
+        }
+        let res = self.ensure_schema().and_then(|_| f());
+        let released = self
+            .store
+            .release_lock()
+            .map_err(|err| Error::Store(Box::new(err)));
+        res.and_then(|res| released.map(|_| res))
+    }
+
+    /// Returns migrations to execute in the order of execution with the history
+    /// they were filtered by.
+    fn pending(
+        &self,
+        up: bool,
+        from: Option<u64>,
+    ) -> Result<(Option<Vec<(u64, CqlFile)>>, HashMap<u64, isize>)> {
+        let migration_history;
+        let migrations_to_execute = match self.slice {
+            Some(ref slice) => {
+                migration_history = self.get_migration_history()?;
+                self.filter_slice(slice, &migration_history, up, from)?
+            }
+            None => {
+                // Try to read migrations dir first
+                let mut migrations = self.discover()?;
+                if up && !self.allow_duplicates {
+                    let ids = duplicate_ids(&migrations);
+                    if !ids.is_empty() {
+                        return Err(Error::Duplicates(ids));
+                    }
+                }
+                if let Some(from) = from {
+                    migrations.retain(|m| m.id >= from);
+                }
+                if up && self.only_failed {
+                    let failures = self.get_failures()?;
+                    migrations.retain(|m| failures.contains(&m.id));
+                }
+
+                migration_history = self.get_migration_history()?;
+                if up && self.verify_checksums {
+                    self.check_checksums(&migrations, &migration_history)?;
+                }
+                self.filter_migrations(migrations, migration_history.clone(), up)?
+            }
+        };
+        let migrations_to_execute = match self.max_version {
+            Some(max) if up => migrations_to_execute.and_then(|mut migrations| {
+                let skipped: Vec<u64> = migrations
+                    .iter()
+                    .map(|(id, _)| *id)
+                    .filter(|id| *id > max)
+                    .collect();
+                if !skipped.is_empty() {
+                    log::info!(
+                        "skipping migrations newer than the max version {}: {:?}",
+                        max,
+                        skipped
+                    );
+                    migrations.retain(|(id, _)| *id <= max);
+                }
+                Some(migrations).filter(|migrations| !migrations.is_empty())
+            }),
+            _ => migrations_to_execute,
+        };
+        Ok((migrations_to_execute, migration_history))
+    }
+
+    /// Fails if a migration not known to the history has a timestamp less than
+    /// or equal to the baseline, e.g. the latest version of the main branch,
+    /// so that a feature branch can't add a back-dated migration.
+    pub fn assert_newer_than(&self, baseline: u64) -> Result<()> {
+        let history = self.get_migration_history()?;
+        let ids: Vec<u64> = self
+            .discover()?
+            .into_iter()
+            .map(|m| m.id)
+            .filter(|id| *id <= baseline && !history.contains_key(id))
+            .collect();
+        if !ids.is_empty() {
+            return Err(Error::NotNewer { ids, baseline });
+        }
+        Ok(())
+    }
+
+    /// Returns ids of migrations which are out of order, see `check_monotonic`.
+    pub fn check_monotonic(&self) -> Result<Vec<u64>> {
+        check_monotonic(&self.path)
+    }
+
+    /// Returns timestamps used by more than one migration, see `check_duplicates`.
+    pub fn check_duplicates(&self) -> Result<Vec<u64>> {
+        check_duplicates(&self.path)
+    }
+
+    /// Brings a database without any history to the latest version by applying
+    /// the consolidated `schema` instead of every migration, then marks all
+    /// migrations as applied, except disabled ones, holding the lock of the store.
+    /// Does nothing if the history is not empty.
+    ///
+    /// The schema must create everything the migrations would,
+    /// including the history table of the store.
+    /// Returns the latest migration id or None if nothing was done.
+    pub fn bootstrap_latest(&self, schema: &str) -> Result<Option<u64>> {
+        self.locked(|| {
+            let mut migrations = Vec::new();
+            for m in self.discover()? {
+                // Disabled migrations are left pending like by `migrate_up`
+                if !m.disabled && !read_cql_file(&m.file(true), &self.parse_options)?.is_disabled()
+                {
+                    migrations.push(m);
+                }
+            }
+            if !self.get_migration_history()?.is_empty() {
+                return Ok(None);
+            }
+            let last_id = match migrations.last() {
+                Some(m) => m.id,
+                None => return Ok(None),
+            };
+
+            let cql = parse_cql(schema.as_bytes(), &self.parse_options)?;
+            if cql.queries.is_empty() {
+                return Err(Error::ParseMigrationFile(
+                    "no CQL found in schema".to_string(),
+                ));
+            }
+            for query in &cql.queries {
+                self.store
+                    .exec(query)
+                    .map_err(|err| Error::Store(Box::new(err)))?;
+            }
+            // The history table was only created by the schema
+            self.store
+                .ensure_schema()
+                .map_err(|err| Error::Store(Box::new(err)))?;
+            for migration in &migrations {
+                self.record(migration.id, true)?;
+            }
+            Ok(Some(last_id))
+        })
+    }
+
+    /// Parses both `up` and `down` files of every migration.
+    pub fn validate(&self) -> Result<()> {
+        for migration in self.discover()? {
+            validate_migration(&migration, &self.parse_options)?;
+        }
+        Ok(())
+    }
+
+    /// Returns all broken migrations with their errors, see `validate_all`.
+    pub fn validate_all(&self) -> Result<Vec<(MigrationDir, Error)>> {
+        let mut res = Vec::new();
+        for migration in self.discover()? {
+            if let Err(err) = validate_migration(&migration, &self.parse_options) {
+                res.push((migration, err));
+            }
+        }
+        Ok(res)
+    }
+
+    /// Returns a hex encoded SHA-256 hash of ids and files of all migrations,
+    /// which only changes when a migration is added, removed or edited.
+    pub fn fingerprint(&self) -> Result<String> {
+        let mut hasher = Sha256::new();
+        for migration in self.discover()? {
+            hasher.update(migration.id.to_be_bytes());
+            migration.hash_files(&mut hasher)?;
+        }
+        Ok(to_hex(hasher))
+    }
+
+    /// Returns queries of migrations `migrate_down_n` would roll back,
+    /// in the order of execution, without executing anything.
+    pub fn plan_down(&self, n: Option<usize>) -> Result<Vec<(u64, Vec<String>)>> {
+        self.plan(false, n)
+    }
+
+    /// Returns queries of up to `n` migrations which would be applied or
+    /// rolled back, in the order of execution, without executing anything.
+    /// The store is only read, preflight hooks are not run.
+    pub fn plan(&self, up: bool, n: Option<usize>) -> Result<Vec<(u64, Vec<String>)>> {
+        let mut res: Vec<(u64, Vec<String>)> = self
+            .pending(up, None)?
+            .0
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(id, cql)| (id, cql.queries))
+            .collect();
+        if let Some(n) = n {
+            res.truncate(n);
+        }
+        Ok(res)
+    }
+
+    /// Checks whether any migration is pending by names of directories only,
+    /// which is cheaper than `describe`, e.g. for readiness probes.
+    /// Migrations disabled by the `Disabled` directive are reported as pending.
+    pub fn has_pending(&self) -> Result<bool> {
+        Ok(self.count_pending_up_to(1)? > 0)
+    }
+
+    /// Counts pending migrations the same way as `has_pending`.
+    pub fn count_pending(&self) -> Result<usize> {
+        self.count_pending_up_to(usize::MAX)
+    }
+
+    /// Counts pending migrations, stopping at `max` of them.
+    fn count_pending_up_to(&self, max: usize) -> Result<usize> {
+        let history = self.get_migration_history()?;
+        let mut count = 0;
+        let ignore = IgnoreList::read(&self.path.join(MIGRATIONS_IGNORE_FILE))?;
+        for entry in fs::read_dir(&self.path)? {
+            if count >= max {
+                break;
+            }
+            let entry = entry?;
+            let name = entry.file_name();
+            let name = match name.to_str() {
+                Some(name) if !ignore.is_ignored(name) => name,
+                _ => continue,
+            };
+            let id = match name.split('_').next().map(str::parse::<u64>) {
+                Some(Ok(id)) => id,
+                _ => continue,
+            };
+            if *history.get(&id).unwrap_or(&0) != 0 {
+                continue;
+            }
+            // Follows symlinks like `discover`
+            let path = entry.path();
+            let metadata = fs::metadata(&path).map_err(|err| {
+                io::Error::new(err.kind(), format!("{}: {}", path.display(), err))
+            })?;
+            if metadata.is_dir() && !path.join(MIGRATION_DISABLED_MARKER).exists() {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Migrates every pending migration up, down and up again without changing
+    /// the history, to check that it can be rolled back. Meant for a sandbox
+    /// keyspace only, as the data touched by migrations is lost.
+    pub fn verify_reversible(&self) -> Result<()> {
+        let migrations = self.discover()?;
+        let history = self.get_migration_history()?;
+        let pending = self
+            .filter_migrations(migrations.clone(), history, true)?
+            .unwrap_or_default();
+        for (id, up) in pending {
+            let dir = migrations.iter().find(|m| m.id == id).unwrap();
+            let down = read_down_file(dir, &self.parse_options)?;
+            for (cql, is_up) in [(up.clone(), true), (down, false), (up, true)] {
+                self.migrate_one(id, cql, is_up, false)
+                    .map_err(|err| Error::Migration {
+                        id,
+                        up: is_up,
+                        source: Box::new(err),
+                    })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns queries of the `up` or `down` file of the migration.
+    pub fn statements(&self, id: u64, up: bool) -> Result<Vec<String>> {
+        let migration = self
+            .discover()?
+            .into_iter()
+            .find(|m| m.id == id)
+            .ok_or(Error::UnknownMigration(id))?;
+        if up {
+            return Ok(parse_cql_file(&migration.file(up), &self.parse_options)?.queries);
+        }
+        match read_down_file(&migration, &self.parse_options)? {
+            cql if cql.is_irreversible() => Err(Error::Irreversible { id }),
+            cql => Ok(cql.queries),
+        }
+    }
+
+    /// Executes only the given statements of the migration, e.g. to step
+    /// through a failing one. The history is not changed.
+    pub fn exec_statements(&self, id: u64, up: bool, range: Range<usize>) -> Result<()> {
+        let statements = self.statements(id, up)?;
+        let len = statements.len();
+        let selected = statements
+            .get(range.clone())
+            .ok_or(Error::StatementsOutOfRange { id, range, len })?;
+        for query in selected {
+            self.store
+                .exec(query)
+                .map_err(|err| Error::Store(Box::new(err)))?;
+        }
+        Ok(())
+    }
+
+    /// Migrates up,
+    /// returns None if database is already up to date.
+    /// See `migrate_outcome` for ids of all executed migrations.
+    pub fn migrate_up(&self) -> Result<Option<u64>> {
+        self.migrate_n(true, None)
+    }
+
+    /// Migrates up starting from the migration with the given id, earlier
+    /// migrations are treated as applied outside of the migrator and left as is,
+    /// returns None if database is already up to date.
+    pub fn migrate_up_from(&self, from: u64) -> Result<Option<u64>> {
+        self.migrate_n_from(true, None, Some(from))
+    }
+
+    /// Same as `migrate_up_from`, returns the summary of the run.
+    pub fn migrate_up_from_outcome(&self, from: u64) -> Result<Option<MigrationOutcome>> {
+        self.run(true, None, Some(from))
+    }
+
+    /// Migrates up or down to the given migration, so that it and all earlier
+    /// migrations are applied and all later ones are rolled back,
+    /// returns None if database is already at that version.
+    /// See `migrate_to_outcome` for ids of all executed migrations.
+    pub fn migrate_to(&self, target: u64) -> Result<Option<u64>> {
+        let outcomes = self.migrate_to_outcome(target)?;
+        Ok(Some(target).filter(|_| !outcomes.is_empty()))
+    }
+
+    /// Same as `migrate_to`, returns summaries of rolling back later migrations
+    /// and applying earlier ones, in that order, or none if database is already
+    /// at that version.
+    pub fn migrate_to_outcome(&self, target: u64) -> Result<Vec<MigrationOutcome>> {
+        for hook in &self.preflight_hooks {
+            hook(&self.store)?;
+        }
+
+        self.locked(|| {
+            let migrations = self.discover()?;
+            if !migrations.iter().any(|m| m.id == target) {
+                return Err(Error::UnknownMigration(target));
+            }
+            // The initial migration is never rolled back, it drops the keyspace
+            let (earlier, later): (Vec<_>, Vec<_>) = migrations
+                .into_iter()
+                .partition(|m| m.initial || m.id <= target);
+
+            let mut history = self.get_migration_history()?;
+            let to_down = self.filter_migrations(later, history.clone(), false)?;
+            let to_up = self.filter_migrations(earlier, history.clone(), true)?;
+            check_reversible(to_down.as_deref().unwrap_or_default())?;
+
+            let mut outcomes = Vec::new();
+            if let Some(to_down) = to_down {
+                let outcome = self.execute_all(to_down, false, true, Instant::now())?;
+                let outcome = outcome.with_versions(&history);
+                for id in &outcome.applied {
+                    history.insert(*id, 0);
+                }
+                outcomes.push(outcome);
+            }
+            if let Some(to_up) = to_up {
+                let outcome = self.execute_all(to_up, true, true, Instant::now())?;
+                outcomes.push(outcome.with_versions(&history));
+            }
+            Ok(outcomes)
+        })
+    }
+
+    /// Migrates up the given migrations all or none of them: if one fails,
+    /// the already applied ones are rolled back in reverse order.
+    ///
+    /// Scylla has no transactions across statements, so this relies on `down`
+    /// files correctly undoing `up` files, and a failed migration is expected
+    /// to leave nothing behind. Already applied migrations are skipped.
+    pub fn migrate_atomic(&self, ids: &[u64]) -> Result<()> {
+        self.locked(|| {
+            let mut migrations = self.discover()?;
+            if let Some(id) = ids
+                .iter()
+                .find(|id| !migrations.iter().any(|m| m.id == **id))
+            {
+                return Err(Error::UnknownMigration(*id));
+            }
+            migrations.retain(|m| ids.contains(&m.id));
+
+            // Read all `down` files first, so that the rollback can't fail to parse them
+            let downs = migrations
+                .iter()
+                .map(|m| Ok((m.id, read_down_file(m, &self.parse_options)?)))
+                .collect::<Result<HashMap<u64, CqlFile>>>()?;
+            let history = self.get_migration_history()?;
+            let ups = self
+                .filter_migrations(migrations, history, true)?
+                .unwrap_or_default();
+            if let Some((id, _)) = ups.iter().find(|(id, _)| downs[id].is_irreversible()) {
+                return Err(Error::Irreversible { id: *id });
+            }
+
+            let mut applied = Vec::with_capacity(ups.len());
+            for (id, cql) in ups {
+                if let Err(err) = self.migrate_one(id, cql, true, true) {
+                    for id in applied.into_iter().rev() {
+                        let down = downs[&id].clone();
+                        if let Err(rollback) = self.migrate_one(id, down, false, true) {
+                            return Err(Error::Rollback {
+                                id,
+                                source: Box::new(err),
+                                rollback: Box::new(rollback),
+                            });
+                        }
+                    }
+                    return Err(err);
+                }
+                applied.push(id);
+            }
+            Ok(())
+        })
+    }
+
+    /// Migrates down,
+    /// returns None if database is already up to date.
+    /// See `migrate_outcome` for ids of all executed migrations.
+    pub fn migrate_down(&self) -> Result<Option<u64>> {
+        self.migrate_n(false, None)
+    }
+
+    /// Migrates up `n` times or less,
+    /// returns None if database is already up to date.
+    pub fn migrate_up_n(&self, n: usize) -> Result<Option<u64>> {
+        self.migrate_n(true, Some(n))
+    }
+
+    /// Migrates down `n` times or less,
+    /// returns None if database is already up to date.
+    pub fn migrate_down_n(&self, n: usize) -> Result<Option<u64>> {
+        self.migrate_n(false, Some(n))
+    }
+
+    /// Returns the net state of every migration found in the history,
+    /// which is the count of `up` events minus the count of `down` events.
+    ///
+    /// A consistent history only contains `1` for applied migrations and `0`
+    /// for rolled back ones, any other value means the history was changed
+    /// outside of the migrator. If every row has a `sequence`, the latest
+    /// event of a migration is used instead, which is always `1` or `0`.
+    pub fn net_state(&self) -> Result<HashMap<u64, isize>> {
+        self.get_migration_history()
+    }
+
+    fn get_migration_history(&self) -> Result<HashMap<u64, isize>> {
+        let migrations = self
+            .store
+            .get_all()
+            .map_err(|err| Error::Store(Box::new(err)))?;
+        Ok(history_counters(migrations))
+    }
+
+    /// Same as `filter_migrations` for migrations given to `with_slice`.
+    fn filter_slice(
+        &self,
+        slice: &[(u64, String, String)],
+        history: &HashMap<u64, isize>,
+        up: bool,
+        from: Option<u64>,
+    ) -> Result<Option<Vec<(u64, CqlFile)>>> {
+        if up && !self.allow_duplicates {
+            let mut ids: Vec<u64> = slice
+                .windows(2)
+                .filter(|pair| pair[0].0 == pair[1].0)
+                .map(|pair| pair[0].0)
+                .collect();
+            ids.dedup();
+            if !ids.is_empty() {
+                return Err(Error::Duplicates(ids));
+            }
+        }
+        let failures = match up && self.only_failed {
+            true => Some(self.get_failures()?),
+            false => None,
+        };
+
+        let mut res: Vec<(u64, CqlFile)> = Vec::new();
+        for (id, q_up, q_down) in slice {
+            let counter = *history.get(id).unwrap_or(&0);
+            if !(up && counter == 0 || (!up && counter == 1))
+                || from.is_some_and(|from| *id < from)
+                || failures.as_ref().is_some_and(|f| !f.contains(id))
+            {
+                continue;
+            }
+
+            // Like files, empty `down` CQL makes the migration irreversible
+            let cql = match up || !q_down.trim().is_empty() {
+                true => parse_cql(
+                    if up { q_up } else { q_down }.as_bytes(),
+                    &self.parse_options,
+                )?,
+                false => irreversible_down(),
+            };
+            // Errors of the `up` CQL must not prevent rolling back
+            let disabled = cql.is_disabled()
+                || !up
+                    && parse_cql(q_up.as_bytes(), &self.parse_options)
+                        .map(|cql| cql.is_disabled())
+                        .unwrap_or(false);
+            if disabled || !self.is_tagged(&cql, up) {
+                continue;
+            }
+            // Like `down` files, `down` CQL with comments only is a no-op
+            if up && cql.queries.is_empty() {
+                return Err(Error::ParseMigrationFile(format!(
+                    "no CQL found in up of migration {}",
+                    id
+                )));
+            }
+            res.push((*id, cql));
+        }
+        order_pending(res, history, up)
+    }
+
+    fn get_failures(&self) -> Result<Vec<u64>> {
+        self.store
+            .get_failures()
+            .map_err(|err| Error::Store(Box::new(err)))
+    }
+
+    /// Compares checksums of applied migrations recorded in the history
+    /// with their current `up` files.
+    fn check_checksums(
+        &self,
+        migrations: &[MigrationDir],
+        history: &HashMap<u64, isize>,
+    ) -> Result<()> {
+        let rows = self
+            .store
+            .get_all()
+            .map_err(|err| Error::Store(Box::new(err)))?
+            .unwrap_or_default();
+        compare_checksums(migrations, history, rows)
+    }
+
+    /// Checks that another process didn't migrate it since the history was read.
+    fn is_still_pending(&self, timestamp: u64, up: bool) -> Result<bool> {
+        let counter = *self.get_migration_history()?.get(&timestamp).unwrap_or(&0);
+        Ok(up && counter == 0 || (!up && counter == 1))
+    }
+
+    fn migrate_one(&self, timestamp: u64, cql: CqlFile, up: bool, add_history: bool) -> Result<()> {
+        if self.audit_sinks.is_empty() {
+            return self.exec_one(timestamp, cql, up, add_history);
+        }
+        let statements = cql.queries.len();
+        let started_at = SystemTime::now();
+        let started = Instant::now();
+        let res = self.exec_one(timestamp, cql, up, add_history);
+        let record = AuditRecord {
+            id: timestamp,
+            up,
+            statements,
+            duration: started.elapsed(),
+            error: res.as_ref().err().map(|err| err.to_string()),
+            started_at,
+            applied_by: self.applied_by.clone(),
+        };
+        for sink in &self.audit_sinks {
+            sink(&record);
+        }
+        res
+    }
+
+    fn exec_one(&self, timestamp: u64, cql: CqlFile, up: bool, add_history: bool) -> Result<()> {
+        if !up && cql.is_irreversible() {
+            return Err(Error::Irreversible { id: timestamp });
+        }
+        let started_at = Instant::now();
+        let timeout = cql.timeout()?;
+        // A timeout too long to be a deadline never passes
+        let deadline = timeout.and_then(|t| Instant::now().checked_add(t));
+        let check_deadline = || match (deadline, timeout) {
+            (Some(deadline), Some(timeout)) if Instant::now() > deadline => Err(Error::Timeout {
+                id: timestamp,
+                timeout,
+            }),
+            _ => Ok(()),
+        };
+
+        let parallel = self
+            .parallel_statements
+            .filter(|&(n, _)| n > 1 && cql.directive(DIRECTIVE_PARALLEL).is_some());
+        if add_history && deadline.is_none() && parallel.is_none() && self.rate_limit.is_none() {
+            let event = self.history_event(timestamp, up)?;
+            return self
+                .store
+                .exec_migration(&cql.queries, &event)
+                .map_err(|err| Error::Store(Box::new(err)));
+        }
+
+        match parallel {
+            Some((n, exec)) => {
+                exec(&self.store, &cql.queries, n, deadline)?;
+            }
+            None => {
+                for query in cql.queries {
+                    check_deadline()?;
+                    let started_at = Instant::now();
+                    match deadline {
+                        Some(deadline) => self.store.exec_until(&query, deadline),
+                        None => self.store.exec(&query),
+                    }
+                    .map_err(|err| Error::Store(Box::new(err)))?;
+                    if let Some(rate) = self.rate_limit {
+                        let pause = rate_limit_pause(query.len(), rate, started_at.elapsed());
+                        thread::sleep(pause);
+                    }
+                }
+            }
+        }
+        check_deadline()?;
+
+        if add_history {
+            let mut event = self.history_event(timestamp, up)?;
+            event.duration = Some(started_at.elapsed());
+            return self
+                .store
+                .record(&event)
+                .map_err(|err| Error::Store(Box::new(err)));
+        }
+        Ok(())
+    }
+
+    fn record(&self, id: u64, up: bool) -> Result<()> {
+        let event = self.history_event(id, up)?;
+        self.store
+            .record(&event)
+            .map_err(|err| Error::Store(Box::new(err)))
+    }
+
+    pub fn execute_migrations(
+        &self,
+        migration_to_execute: Vec<(u64, CqlFile)>,
         up: bool,
         n: Option<usize>,
     ) -> Result<Option<u64>> {
+        Ok(Some(self.execute(migration_to_execute, up, n)?.last_id))
+    }
+
+    /// Same as `execute_migrations`, returns the summary of the run.
+    pub fn execute_migrations_outcome(
+        &self,
+        migration_to_execute: Vec<(u64, CqlFile)>,
+        up: bool,
+        n: Option<usize>,
+    ) -> Result<MigrationOutcome> {
+        self.execute(migration_to_execute, up, n)
+    }
+
+    fn execute(
+        &self,
+        migration_to_execute: Vec<(u64, CqlFile)>,
+        up: bool,
+        n: Option<usize>,
+    ) -> Result<MigrationOutcome> {
+        let started_at = Instant::now();
         let (last_id, take_n) = match n {
             Some(v) => {
                 if migration_to_execute.len() > v {
@@ -313,16 +1681,347 @@ where
             ),
         };
 
+        if !up {
+            // Fail before rolling back anything
+            check_reversible(&migration_to_execute[..take_n])?;
+        }
         let add_history = up || take_n != migration_to_execute.len();
-        for (timestamp, queries) in migration_to_execute.into_iter().take(take_n) {
-            self.migrate_one(timestamp, queries, up, add_history)?;
+        let migrations = migration_to_execute.into_iter().take(take_n);
+        Ok(MigrationOutcome {
+            last_id,
+            ..self.execute_all(migrations, up, add_history, started_at)?
+        })
+    }
+
+    /// Executes the migrations in the given order, the failure of one is recorded
+    /// by the store and ends the run.
+    fn execute_all<I>(
+        &self,
+        migrations: I,
+        up: bool,
+        add_history: bool,
+        started_at: Instant,
+    ) -> Result<MigrationOutcome>
+    where
+        I: IntoIterator<Item = (u64, CqlFile)>,
+    {
+        let mut applied = Vec::new();
+        let mut durations = Vec::new();
+        let mut statements = 0;
+        for (timestamp, cql) in migrations {
+            if self.recheck_history && !self.is_still_pending(timestamp, up)? {
+                continue;
+            }
+            statements += cql.queries.len();
+            let migration_started_at = Instant::now();
+            if let Err(err) = self.migrate_one(timestamp, cql, up, add_history) {
+                if let Err(store_err) = self.store.record_failure(timestamp, up, &err.to_string()) {
+                    log::warn!(
+                        "failed to record the failure of {}: {}",
+                        timestamp,
+                        store_err
+                    );
+                }
+                return Err(err);
+            }
+            applied.push(timestamp);
+            durations.push(migration_started_at.elapsed());
+        }
+
+        Ok(MigrationOutcome {
+            up,
+            last_id: applied.last().copied().unwrap_or_default(),
+            applied,
+            durations,
+            from: None,
+            to: None,
+            statements,
+            elapsed: started_at.elapsed(),
+        })
+    }
+}
+
+impl<'a, S> Migrator<'a, S> {
+    fn filter_migrations(
+        &self,
+        migrations: Vec<MigrationDir>,
+        history: HashMap<u64, isize>,
+        up: bool,
+    ) -> Result<Option<Vec<(u64, CqlFile)>>> {
+        // Only the file of the requested direction is read,
+        // use `validate` to check both of them.
+        let mut res: Vec<(u64, CqlFile)> = Vec::new();
+        for m in migrations {
+            let counter = *history.get(&m.id).unwrap_or(&0);
+            if !(up && counter == 0 || (!up && counter == 1)) || m.disabled {
+                continue;
+            }
+
+            let path = m.file(up);
+            let cql = match up {
+                true => read_cql_file(&path, &self.parse_options)?,
+                false => read_down_file(&m, &self.parse_options)?,
+            };
+            if self.is_disabled(&m, &cql, up) || !self.is_tagged(&cql, up) {
+                continue;
+            }
+            match up {
+                true => res.push((m.id, check_queries(&path, cql)?)),
+                false => res.push((m.id, cql)),
+            }
+        }
+        order_pending(res, &history, up)
+    }
+
+    /// Checks the tag filter set with `only_tag`, which applies to the `up` direction only.
+    fn is_tagged(&self, cql: &CqlFile, up: bool) -> bool {
+        match self.tag.as_deref() {
+            Some(tag) if up => cql.has_tag(tag),
+            _ => true,
+        }
+    }
+
+    /// Returns migrations of the directory of the migrator with its file names,
+    /// see `discover`.
+    pub(crate) fn discover(&self) -> Result<Vec<MigrationDir>> {
+        let mut migrations = discover(&self.path)?;
+        if let Some(ref names) = self.file_names {
+            for m in &mut migrations {
+                m.file_names = Some(names.clone());
+                m.initial = is_initial(&m.name, &m.file(true));
+            }
+            migrations.sort_by_key(|m| (!m.initial, m.id));
+        }
+        Ok(migrations)
+    }
+
+    /// Returns the directory of the migration by its id without reading files.
+    fn migration_dir_path(&self, id: u64) -> Result<Option<PathBuf>> {
+        for entry in fs::read_dir(&self.path)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let prefix = name.to_str().and_then(|name| name.split('_').next());
+            if prefix.and_then(|prefix| prefix.parse::<u64>().ok()) == Some(id) {
+                return Ok(Some(entry.path()));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Checks the `Disabled` directive, which disables both directions
+    /// when written in the `up` file.
+    fn is_disabled(&self, migration: &MigrationDir, cql: &CqlFile, up: bool) -> bool {
+        if migration.disabled || cql.is_disabled() {
+            return true;
+        }
+        // Errors of the `up` file must not prevent rolling back
+        !up && read_cql_file(&migration.file(true), &self.parse_options)
+            .map(|cql| cql.is_disabled())
+            .unwrap_or(false)
+    }
+
+    fn history_event(&self, id: u64, up: bool) -> Result<HistoryEvent> {
+        let (up_content, down_content) = match up {
+            true => (self.file_content(id, true)?, self.file_content(id, false)?),
+            false => (None, None),
+        };
+        let name = match self.slice {
+            Some(_) => None,
+            None => self
+                .migration_dir_path(id)?
+                .and_then(|dir| dir.file_name()?.to_str().map(String::from)),
+        };
+        Ok(HistoryEvent {
+            id,
+            up,
+            name,
+            applied_at: Some(SystemTime::now()),
+            duration: None,
+            applied_by: self.applied_by.clone(),
+            checksum: up_content.as_ref().map(|c| checksum(c.as_bytes())),
+            down_checksum: down_content.map(|c| checksum(c.as_bytes())),
+            content: up_content.filter(|_| self.store_content),
+        })
+    }
+
+    /// Returns the `up` or `down` CQL of the migration, from the slice or the file.
+    /// A missing `down` file is empty.
+    fn file_content(&self, id: u64, up: bool) -> Result<Option<String>> {
+        if let Some(ref slice) = self.slice {
+            let migration = slice.iter().find(|(i, _, _)| *i == id);
+            return Ok(migration.map(|(_, q_up, q_down)| if up { q_up } else { q_down }.clone()));
         }
+        let dir = match self.migration_dir_path(id)? {
+            Some(dir) => dir,
+            None => return Ok(None),
+        };
+        match fs::read_to_string(named_file(&dir, up, &self.file_names)) {
+            Err(ref err) if !up && err.kind() == io::ErrorKind::NotFound => Ok(Some(String::new())),
+            res => Ok(Some(res?)),
+        }
+    }
+}
 
-        Ok(Some(last_id))
+/// Folds history events into counters of migrations, 1 for applied ones.
+fn history_counters<R: MigrationRow>(migrations: Option<Vec<R>>) -> HashMap<u64, isize> {
+    match migrations {
+        Some(mut migrations) => {
+            // Most of migrations are only migrated up once
+            let acc = HashMap::with_capacity(migrations.len());
+            if migrations.iter().all(|m| m.sequence().is_some()) {
+                // Events are ordered, so the latest one wins
+                migrations.sort_by_key(|m| m.sequence());
+                migrations.into_iter().fold(acc, |mut acc, m| {
+                    acc.insert(m.id(), if m.is_up() { 1 } else { 0 });
+                    acc
+                })
+            } else {
+                migrations.into_iter().fold(acc, |mut acc, m| {
+                    let increment = if m.is_up() { 1 } else { -1 };
+                    *acc.entry(m.id()).or_insert(0) += increment;
+                    acc
+                })
+            }
+        }
+        None => HashMap::new(),
     }
 }
 
-fn is_cql_comment_line(line: &str) -> bool {
-    let comment_slice = &line[..COMMENT_LENGTH];
-    comment_slice == COMMENT_LINE_TYPE_1 || comment_slice == COMMENT_LINE_TYPE_2
+/// Compares checksums of applied migrations recorded in the given rows of
+/// the history with their current files, see `Migrator::verify_checksums`.
+fn compare_checksums<R: MigrationRow>(
+    migrations: &[MigrationDir],
+    history: &HashMap<u64, isize>,
+    mut rows: Vec<R>,
+) -> Result<()> {
+    if rows.iter().all(|row| row.sequence().is_some()) {
+        rows.sort_by_key(|row| row.sequence());
+    }
+    // The latest application wins
+    let mut checksums = HashMap::new();
+    for row in rows.iter().filter(|row| row.is_up()) {
+        checksums.insert(row.id(), (row.checksum(), row.down_checksum()));
+    }
+
+    for m in migrations {
+        let (up, down) = match checksums.get(&m.id) {
+            Some(expected) if history.get(&m.id) == Some(&1) => *expected,
+            _ => continue,
+        };
+        for (is_up, expected) in [(true, up), (false, down)] {
+            let expected = match expected {
+                Some(expected) => expected,
+                None => continue,
+            };
+            let actual = match is_up {
+                true => m.up_checksum()?,
+                false => m.down_checksum()?,
+            };
+            if actual != expected {
+                return Err(Error::ChecksumMismatch {
+                    id: m.id,
+                    up: is_up,
+                    expected: expected.to_string(),
+                    actual,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Fails if any of the `down` files is irreversible.
+fn check_reversible(downs: &[(u64, CqlFile)]) -> Result<()> {
+    match downs.iter().find(|(_, cql)| cql.is_irreversible()) {
+        Some((id, _)) => Err(Error::Irreversible { id: *id }),
+        None => Ok(()),
+    }
+}
+
+/// Puts pending migrations in the order of execution, returns None if there are none.
+fn order_pending(
+    mut migrations: Vec<(u64, CqlFile)>,
+    history: &HashMap<u64, isize>,
+    up: bool,
+) -> Result<Option<Vec<(u64, CqlFile)>>> {
+    if migrations.is_empty() {
+        return Ok(None);
+    }
+    if up {
+        check_dependencies(&migrations, history)?;
+    } else {
+        migrations.reverse();
+    }
+    Ok(Some(migrations))
+}
+
+/// Checks that dependencies of every migration are applied or migrated before it.
+fn check_dependencies(migrations: &[(u64, CqlFile)], history: &HashMap<u64, isize>) -> Result<()> {
+    for (i, (id, cql)) in migrations.iter().enumerate() {
+        for depends_on in cql.depends_on()? {
+            let is_applied = history.get(&depends_on) == Some(&1);
+            if !is_applied && !migrations[..i].iter().any(|(id, _)| *id == depends_on) {
+                return Err(Error::Dependency {
+                    id: *id,
+                    depends_on,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns how long to wait after sending `len` bytes during `elapsed`
+/// to stay under the rate.
+fn rate_limit_pause(len: usize, bytes_per_second: u64, elapsed: Duration) -> Duration {
+    let budget = Duration::from_secs_f64(len as f64 / bytes_per_second as f64);
+    budget.saturating_sub(elapsed)
+}
+
+fn exec_parallel<S>(
+    store: &S,
+    queries: &[String],
+    threads: usize,
+    deadline: Option<Instant>,
+) -> Result<()>
+where
+    S: Store + Sync,
+    S::Error: Send,
+{
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let results: Vec<std::result::Result<(), S::Error>> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads.min(queries.len()))
+            .map(|_| {
+                scope.spawn(|| {
+                    // Stop taking new queries as soon as one of them fails
+                    while !failed.load(Ordering::Relaxed) {
+                        let query = match queries.get(next.fetch_add(1, Ordering::Relaxed)) {
+                            Some(query) => query,
+                            None => break,
+                        };
+                        let res = match deadline {
+                            Some(deadline) => store.exec_until(query, deadline),
+                            None => store.exec(query),
+                        };
+                        if res.is_err() {
+                            failed.store(true, Ordering::Relaxed);
+                            return res;
+                        }
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap_or_else(|err| panic::resume_unwind(err)))
+            .collect()
+    });
+
+    results
+        .into_iter()
+        .collect::<std::result::Result<Vec<()>, S::Error>>()
+        .map_err(|err| Error::Store(Box::new(err)))?;
+    Ok(())
 }
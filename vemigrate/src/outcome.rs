@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+/// Summary of a migration run, see `Migrator::migrate_outcome`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MigrationOutcome {
+    pub up: bool,
+    /// Ids of executed migrations in the order of execution.
+    pub applied: Vec<u64>,
+    /// Duration of every migration in `applied`.
+    pub durations: Vec<Duration>,
+    /// Latest applied migration before the run.
+    pub from: Option<u64>,
+    /// Latest applied migration after the run.
+    pub to: Option<u64>,
+    /// Total count of executed statements.
+    pub statements: usize,
+    pub elapsed: Duration,
+    pub(crate) last_id: u64,
+}
+
+/// Statistics of durations of migrations in a run, see `MigrationOutcome::timings`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Timings {
+    pub min: Duration,
+    pub max: Duration,
+    pub mean: Duration,
+    /// 95th percentile by the nearest rank.
+    pub p95: Duration,
+    /// Id of the migration which took `max`.
+    pub slowest: u64,
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "min {:.2}s, mean {:.2}s, p95 {:.2}s, max {:.2}s by migration {}",
+            self.min.as_secs_f64(),
+            self.mean.as_secs_f64(),
+            self.p95.as_secs_f64(),
+            self.max.as_secs_f64(),
+            self.slowest
+        )
+    }
+}
+
+impl MigrationOutcome {
+    /// Returns the last executed migration, or None if nothing was executed.
+    pub fn last(&self) -> Option<u64> {
+        self.applied.last().copied()
+    }
+
+    /// Returns statistics of durations of executed migrations,
+    /// or None if nothing was executed.
+    pub fn timings(&self) -> Option<Timings> {
+        let (slowest, max) = self
+            .applied
+            .iter()
+            .zip(&self.durations)
+            .max_by_key(|(_, duration)| **duration)?;
+        let mut sorted = self.durations.clone();
+        sorted.sort_unstable();
+        let p95_rank = (sorted.len() * 95).div_ceil(100);
+        Some(Timings {
+            min: sorted[0],
+            max: *max,
+            mean: sorted.iter().sum::<Duration>() / sorted.len() as u32,
+            p95: sorted[p95_rank - 1],
+            slowest: *slowest,
+        })
+    }
+
+    /// Fills `from` and `to` using the history read before the run.
+    pub(crate) fn with_versions(mut self, history: &HashMap<u64, isize>) -> Self {
+        let applied_before = || {
+            history
+                .iter()
+                .filter(|(_, counter)| **counter == 1)
+                .map(|(id, _)| *id)
+        };
+        self.from = applied_before().max();
+        self.to = if self.up {
+            applied_before().chain(self.applied.iter().copied()).max()
+        } else {
+            applied_before()
+                .filter(|id| !self.applied.contains(id))
+                .max()
+        };
+        self
+    }
+}
+
+impl fmt::Display for MigrationOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let version = |v: Option<u64>| v.map_or_else(|| "none".to_string(), |v| v.to_string());
+        write!(
+            f,
+            "{} {} migration(s), version {} -> {}, {} statement(s) in {:.2}s",
+            if self.up { "applied" } else { "rolled back" },
+            self.applied.len(),
+            version(self.from),
+            version(self.to),
+            self.statements,
+            self.elapsed.as_secs_f64()
+        )
+    }
+}
@@ -0,0 +1,725 @@
+use crate::{Error, Result};
+
+use std::borrow::Cow;
+use std::io::BufRead;
+use std::time::Duration;
+
+const COMMENT_LENGTH: usize = 2;
+const COMMENT_LINE_TYPE_1: &str = "--";
+const COMMENT_LINE_TYPE_2: &str = "//";
+const BLOCK_COMMENT_START: &str = "/*";
+const BLOCK_COMMENT_END: &str = "*/";
+const QUERIES_SEPARATOR: char = ';';
+const LINE_CONTINUATION: char = '\\';
+const DOLLAR_QUOTE: &str = "$$";
+
+/// Marker which turns a comment line into a directive, e.g. `-- +migrate Timeout: 30s`.
+const DIRECTIVE_MARKER: &str = "+migrate";
+const DIRECTIVE_VALUE_SEPARATOR: char = ':';
+
+pub const DIRECTIVE_TIMEOUT: &str = "timeout";
+pub const DIRECTIVE_PARALLEL: &str = "parallel";
+pub const DIRECTIVE_DISABLED: &str = "disabled";
+pub const DIRECTIVE_INITIAL: &str = "initial";
+pub const DIRECTIVE_DEPENDS_ON: &str = "dependson";
+pub const DIRECTIVE_TAGS: &str = "tags";
+pub const DIRECTIVE_IRREVERSIBLE: &str = "irreversible";
+
+const BATCH_BEGIN: &[&str] = &["begin", "batch"];
+const BATCH_BEGIN_TYPED: &[&str] = &["unlogged", "logged", "counter"];
+const BATCH_APPLY: &[&str] = &["apply", "batch"];
+
+/// Keywords starting a statement, used to detect a missing terminator.
+const STATEMENT_KEYWORDS: &[&str] = &[
+    "alter", "apply", "begin", "create", "delete", "drop", "grant", "insert", "revoke", "select",
+    "truncate", "update", "use",
+];
+
+/// Words and characters ending a line of a statement which continues on the
+/// next one, e.g. `CREATE MATERIALIZED VIEW v AS` before its `SELECT`.
+const CONTINUATION_KEYWORDS: &[&str] = &["and", "as", "from", "or", "where", "with"];
+const CONTINUATION_CHARS: &[char] = &[',', '(', '=', '+', '-'];
+
+/// Instruction for the migrator written as a comment in a migration file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Directive {
+    /// Lowercased directive name, e.g. `timeout`.
+    pub name: String,
+    /// Value after the `:` separator, if any.
+    pub value: Option<String>,
+}
+
+/// Queries and directives parsed from a single migration file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CqlFile {
+    pub queries: Vec<String>,
+    pub directives: Vec<Directive>,
+}
+
+impl CqlFile {
+    /// Returns the directive with the given name, ignoring case.
+    pub fn directive(&self, name: &str) -> Option<&Directive> {
+        self.directives.iter().find(|d| is_keyword(&d.name, name))
+    }
+
+    /// Checks for `-- +migrate Disabled` directive.
+    pub fn is_disabled(&self) -> bool {
+        self.directive(DIRECTIVE_DISABLED).is_some()
+    }
+
+    /// Checks for `-- +migrate Irreversible` directive of a `down` file,
+    /// which is implied by a missing or empty `down` file.
+    pub fn is_irreversible(&self) -> bool {
+        self.directive(DIRECTIVE_IRREVERSIBLE).is_some()
+    }
+
+    /// Returns ids listed by `-- +migrate DependsOn: 1700000000, 1700000001` directives.
+    pub fn depends_on(&self) -> Result<Vec<u64>> {
+        let mut res = Vec::new();
+        for directive in self
+            .directives
+            .iter()
+            .filter(|d| is_keyword(&d.name, DIRECTIVE_DEPENDS_ON))
+        {
+            let value = directive.value.as_deref().unwrap_or_default();
+            for id in value.split(',') {
+                match id.trim().parse::<u64>() {
+                    Ok(id) => res.push(id),
+                    Err(_) => {
+                        return Err(Error::ParseMigrationFile(format!(
+                            "invalid dependency '{}', expected migration ids separated by commas",
+                            value
+                        )))
+                    }
+                }
+            }
+        }
+        Ok(res)
+    }
+
+    /// Returns tags listed by `-- +migrate Tags: data, slow` directives.
+    pub fn tags(&self) -> Vec<&str> {
+        self.directives
+            .iter()
+            .filter(|d| is_keyword(&d.name, DIRECTIVE_TAGS))
+            .filter_map(|d| d.value.as_deref())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+            .collect()
+    }
+
+    /// Checks whether the tag is listed by `-- +migrate Tags` directives.
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags().contains(&tag)
+    }
+
+    /// Returns the duration of `-- +migrate Timeout: 30s` directive.
+    pub fn timeout(&self) -> Result<Option<Duration>> {
+        match self.directive(DIRECTIVE_TIMEOUT) {
+            Some(directive) => {
+                let value = directive.value.as_deref().unwrap_or_default();
+                match parse_duration(value) {
+                    Some(duration) => Ok(Some(duration)),
+                    None => Err(Error::ParseMigrationFile(format!(
+                        "invalid timeout '{}', expected a value like 500ms, 30s, 5m or 1h",
+                        value
+                    ))),
+                }
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// Options of `parse_cql`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseOptions {
+    /// Terminator of statements, `;` by default.
+    ///
+    /// A custom delimiter, e.g. `GO` or `//`, is removed from the statement
+    /// and makes `;` a regular character, which is needed for function bodies.
+    pub statement_delimiter: String,
+    /// Fails if a statement starts before the previous one was terminated,
+    /// instead of merging them.
+    pub require_terminators: bool,
+    /// Allows the last statement to miss the terminator
+    /// when `require_terminators` is set.
+    pub allow_unterminated_last: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            statement_delimiter: QUERIES_SEPARATOR.to_string(),
+            require_terminators: false,
+            allow_unterminated_last: false,
+        }
+    }
+}
+
+impl ParseOptions {
+    fn is_default_delimiter(&self) -> bool {
+        self.statement_delimiter.len() == 1
+            && self.statement_delimiter.starts_with(QUERIES_SEPARATOR)
+    }
+}
+
+/// Parses CQL queries and `-- +migrate` directives from the given reader.
+///
+/// Queries are separated by `;`, except inside of `BEGIN BATCH ... APPLY BATCH;`
+/// which is kept as a single query, and inside of string literals, quoted
+/// identifiers and `$$` function bodies, which may span multiple lines.
+///
+/// Block comments `/* ... */` may span multiple lines and appear anywhere
+/// outside of quotes, comments inside of quotes and `/*` inside of line
+/// comments are ignored. Line comments may also end a line of code.
+/// Lines of a query are joined with a space, or with a line break inside of
+/// a string literal. A line ending with `\` outside of a string literal is
+/// joined with the next one without a separator.
+pub fn parse_cql<R: BufRead>(reader: R, options: &ParseOptions) -> Result<CqlFile> {
+    let mut statements = StatementReader::new(reader, options);
+    let queries = statements.by_ref().collect::<Result<Vec<_>>>()?;
+    Ok(CqlFile {
+        queries,
+        directives: statements.directives,
+    })
+}
+
+/// Iterator over queries of a migration file read one line at a time,
+/// so that only the current query is kept in memory, see `parse_cql`.
+///
+/// Directives found so far are available with `directives`.
+pub struct StatementReader<'a, R> {
+    reader: R,
+    options: &'a ParseOptions,
+    buf: String,
+    current: Option<String>,
+    directives: Vec<Directive>,
+    is_new_query: bool,
+    is_batch: bool,
+    is_block_comment: bool,
+    is_continuation: bool,
+    // Quote of the string literal or identifier continuing on the next line
+    quote: Option<Quote>,
+    line_number: usize,
+    is_done: bool,
+}
+
+impl<'a, R: BufRead> StatementReader<'a, R> {
+    pub fn new(reader: R, options: &'a ParseOptions) -> Self {
+        StatementReader {
+            reader,
+            options,
+            buf: String::new(),
+            current: None,
+            directives: Vec::new(),
+            is_new_query: false,
+            is_batch: false,
+            is_block_comment: false,
+            is_continuation: false,
+            quote: None,
+            line_number: 0,
+            is_done: false,
+        }
+    }
+
+    /// Returns directives read so far, all of them once the iterator is exhausted.
+    pub fn directives(&self) -> &[Directive] {
+        &self.directives
+    }
+
+    fn read_line(&mut self) -> Result<()> {
+        let options = self.options;
+        // A string literal spanning lines may contain anything that looks like CQL syntax
+        let in_string = self.quote.is_some();
+        let uncommented;
+        let trimmed = if in_string {
+            self.buf.trim()
+        } else {
+            uncommented = skip_block_comments(self.buf.trim(), &mut self.is_block_comment);
+            &uncommented
+        };
+        let (code, quote) = strip_line_comment(trimmed, self.quote, &options.statement_delimiter);
+        let ends_in_string = quote.is_some();
+        if !in_string
+            && !options.is_default_delimiter()
+            && strip_delimiter(trimmed, &options.statement_delimiter) == Some("")
+        {
+            // Delimiter on its own line, it may look like a `//` comment
+            self.is_new_query = true;
+        } else if !in_string && !trimmed.is_empty() && is_cql_comment_line(trimmed) {
+            if let Some(directive) = parse_directive(trimmed) {
+                self.directives.push(directive);
+            }
+        } else if !code.is_empty() {
+            let is_terminated = self.is_new_query || self.is_continuation || in_string;
+            if options.require_terminators && !is_terminated {
+                check_terminated(self.current.as_ref(), code, self.line_number, options)?;
+            }
+            let starts_query = self.current.is_none();
+
+            let line = if options.is_default_delimiter() {
+                if starts_query {
+                    self.is_batch =
+                        starts_with_keywords(code, BATCH_BEGIN) || starts_with_typed_batch(code);
+                }
+                // The separator at the end of the line is a part of the string
+                // if the line ends inside of a string literal
+                self.is_new_query = !ends_in_string
+                    && code.ends_with(QUERIES_SEPARATOR)
+                    && (!self.is_batch || ends_with_keywords(code, BATCH_APPLY));
+                code
+            } else {
+                let stripped = if ends_in_string {
+                    None
+                } else {
+                    strip_delimiter(code, &options.statement_delimiter)
+                };
+                match stripped {
+                    Some(line) => {
+                        self.is_new_query = true;
+                        line
+                    }
+                    None => {
+                        self.is_new_query = false;
+                        code
+                    }
+                }
+            };
+
+            let joins_directly = self.is_continuation;
+            let (line, continues) = strip_continuation(line, ends_in_string);
+            self.is_continuation = continues;
+            self.quote = quote;
+
+            match self.current.as_mut() {
+                None => self.current = Some(line.to_string()),
+                Some(query) => {
+                    // Keep words of adjacent lines apart, `text)WITH` is not valid CQL,
+                    // line breaks of string literals are a part of the value
+                    if !query.is_empty() && !joins_directly {
+                        query.push(if in_string { '\n' } else { ' ' });
+                    }
+                    query.push_str(line);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a, R: BufRead> Iterator for StatementReader<'a, R> {
+    type Item = Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.is_done {
+            self.buf.clear();
+            let line = self
+                .reader
+                .read_line(&mut self.buf)
+                .map_err(Error::from)
+                .and_then(|bytes_count| {
+                    if bytes_count > 0 {
+                        self.line_number += 1;
+                        self.read_line()?;
+                    }
+                    Ok(bytes_count)
+                });
+            match line {
+                Ok(0) => {
+                    self.is_done = true;
+                    let options = self.options;
+                    let query = self.current.take()?;
+                    // The last query is still open when no terminator has been seen
+                    if options.require_terminators && !options.allow_unterminated_last {
+                        return Some(Err(Error::ParseMigrationFile(format!(
+                            "line {}: the last statement is not terminated with `{}`",
+                            self.line_number, options.statement_delimiter
+                        ))));
+                    }
+                    return Some(Ok(query));
+                }
+                Ok(_) if self.is_new_query && self.current.is_some() => {
+                    return self.current.take().map(Ok);
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    self.is_done = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Fails if the line starts a new statement while the current one is not terminated,
+/// unless the current one clearly continues on the line.
+fn check_terminated(
+    current: Option<&String>,
+    line: &str,
+    line_number: usize,
+    options: &ParseOptions,
+) -> Result<()> {
+    let current = match current {
+        Some(current) => current,
+        None => return Ok(()),
+    };
+    // Statements of a batch are terminated by `APPLY BATCH`
+    let is_batch = starts_with_keywords(current, BATCH_BEGIN) || starts_with_typed_batch(current);
+    let continues = current.ends_with(CONTINUATION_CHARS)
+        || is_alter_header(current)
+        || current
+            .split_whitespace()
+            .next_back()
+            .is_some_and(|word| CONTINUATION_KEYWORDS.iter().any(|kw| is_keyword(word, kw)));
+    let starts_statement = line
+        .split_whitespace()
+        .next()
+        .is_some_and(|word| STATEMENT_KEYWORDS.iter().any(|kw| is_keyword(word, kw)));
+    if !is_batch && !continues && starts_statement {
+        return Err(Error::ParseMigrationFile(format!(
+            "line {}: `{}` starts before the previous statement is terminated with `{}`",
+            line_number, line, options.statement_delimiter
+        )));
+    }
+    Ok(())
+}
+
+/// Checks for `ALTER TABLE <name>` or `ALTER TYPE <name>` missing the alteration,
+/// which may start with a statement keyword like `DROP c` or `ALTER c TYPE text`.
+fn is_alter_header(current: &str) -> bool {
+    let words: Vec<&str> = current.split_whitespace().collect();
+    let name = match words.as_slice() {
+        [alter, kind, name @ ..]
+            if is_keyword(alter, "alter")
+                && (is_keyword(kind, "table") || is_keyword(kind, "type")) =>
+        {
+            name
+        }
+        _ => return false,
+    };
+    match name {
+        [_] => true,
+        [if_, exists, _] => is_keyword(if_, "if") && is_keyword(exists, "exists"),
+        _ => false,
+    }
+}
+
+/// Strips `\` at the end of the line which joins the next line without
+/// a separator, unless the line ends inside of a string literal.
+fn strip_continuation(line: &str, ends_in_string: bool) -> (&str, bool) {
+    match line.strip_suffix(LINE_CONTINUATION) {
+        Some(rest) if !ends_in_string => (rest, true),
+        _ => (line, false),
+    }
+}
+
+/// Skips parts of the line inside of block comments and updates whether
+/// the block comment continues on the next line. Code around a comment
+/// is joined with a single space.
+fn skip_block_comments<'a>(line: &'a str, is_block_comment: &mut bool) -> Cow<'a, str> {
+    if !*is_block_comment && (is_cql_comment_line(line) || !line.contains(BLOCK_COMMENT_START)) {
+        return Cow::Borrowed(line);
+    }
+
+    let mut res = String::with_capacity(line.len());
+    let mut rest = line;
+    let mut quote = None;
+    loop {
+        if *is_block_comment {
+            match rest.find(BLOCK_COMMENT_END) {
+                Some(pos) => {
+                    *is_block_comment = false;
+                    rest = &rest[pos + BLOCK_COMMENT_END.len()..];
+                }
+                None => break,
+            }
+        } else {
+            let start = find_block_comment(rest, &mut quote);
+            let code = rest[..start.unwrap_or(rest.len())].trim();
+            if !res.is_empty() && !code.is_empty() {
+                res.push(' ');
+            }
+            res.push_str(code);
+            match start {
+                Some(pos) => {
+                    *is_block_comment = true;
+                    rest = &rest[pos + BLOCK_COMMENT_START.len()..];
+                }
+                None => break,
+            }
+        }
+    }
+    Cow::Owned(res)
+}
+
+/// Quotes of string literals and identifiers, which may contain what looks
+/// like comments or other quotes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Quote {
+    Single,
+    Double,
+    Dollar,
+}
+
+impl Quote {
+    fn as_str(self) -> &'static str {
+        match self {
+            Quote::Single => "'",
+            Quote::Double => "\"",
+            Quote::Dollar => DOLLAR_QUOTE,
+        }
+    }
+
+    /// Returns the length of the quote the rest of the line starts with,
+    /// which opens or closes a literal, and updates the open quote.
+    /// Quotes are escaped by doubling them, so only the parity matters.
+    fn toggle(rest: &str, quote: &mut Option<Quote>) -> Option<usize> {
+        let found = match *quote {
+            Some(open) => Some(open).filter(|open| rest.starts_with(open.as_str())),
+            None => [Quote::Dollar, Quote::Single, Quote::Double]
+                .iter()
+                .copied()
+                .find(|q| rest.starts_with(q.as_str())),
+        }?;
+        *quote = if quote.is_some() { None } else { Some(found) };
+        Some(found.as_str().len())
+    }
+}
+
+/// Returns the position of `/*` outside of quotes, unless a `--`
+/// line comment starts before it, updates the quote continuing after it.
+fn find_block_comment(line: &str, quote: &mut Option<Quote>) -> Option<usize> {
+    let mut pos = 0;
+    while let Some(rest) = line.get(pos..).filter(|rest| !rest.is_empty()) {
+        if let Some(len) = Quote::toggle(rest, quote) {
+            pos += len;
+            continue;
+        }
+        if quote.is_none() && rest.starts_with(COMMENT_LINE_TYPE_1) {
+            return None;
+        } else if quote.is_none() && rest.starts_with(BLOCK_COMMENT_START) {
+            return Some(pos);
+        }
+        pos += rest.chars().next().map_or(1, char::len_utf8);
+    }
+    None
+}
+
+/// Strips a `--` or `//` comment at the end of the line which starts outside
+/// of quotes and returns the quote the line ends in, if any.
+/// A custom delimiter starting like a comment is kept.
+fn strip_line_comment<'a>(
+    line: &'a str,
+    quote: Option<Quote>,
+    delimiter: &str,
+) -> (&'a str, Option<Quote>) {
+    let mut quote = quote;
+    let mut pos = 0;
+    while let Some(rest) = line.get(pos..).filter(|rest| !rest.is_empty()) {
+        if let Some(len) = Quote::toggle(rest, &mut quote) {
+            pos += len;
+            continue;
+        }
+        if quote.is_none() && is_cql_comment_line(rest) && !rest.starts_with(delimiter) {
+            return (line[..pos].trim_end(), None);
+        }
+        pos += rest.chars().next().map_or(1, char::len_utf8);
+    }
+    (line, quote)
+}
+
+/// Strips a custom delimiter from the end of the line, returns None if the line
+/// doesn't end with it. Word delimiters like `GO` must be separated by whitespace.
+fn strip_delimiter<'a>(line: &'a str, delimiter: &str) -> Option<&'a str> {
+    let split_at = line.len().checked_sub(delimiter.len())?;
+    let (rest, tail) = (line.get(..split_at)?, line.get(split_at..)?);
+    if !is_keyword(tail, delimiter) {
+        return None;
+    }
+    let is_word = delimiter.chars().all(char::is_alphanumeric);
+    if is_word && !rest.is_empty() && !rest.ends_with(char::is_whitespace) {
+        return None;
+    }
+    Some(rest.trim_end())
+}
+
+/// Checks whether the statement drops a keyspace, table, column or any other
+/// object, or truncates a table.
+pub fn is_destructive(statement: &str) -> bool {
+    let mut words = statement.split_whitespace();
+    match words.next() {
+        Some(word) if is_keyword(word, "drop") || is_keyword(word, "truncate") => true,
+        Some(word) if is_keyword(word, "alter") => words.any(|w| is_keyword(w, "drop")),
+        _ => false,
+    }
+}
+
+/// Parses a directive from a comment line,
+/// returns None if the comment is not a directive.
+fn parse_directive(line: &str) -> Option<Directive> {
+    let comment = line[COMMENT_LENGTH..].trim_start();
+    let marker = comment.get(..DIRECTIVE_MARKER.len())?;
+    if !is_keyword(marker, DIRECTIVE_MARKER) {
+        return None;
+    }
+
+    let body = comment[DIRECTIVE_MARKER.len()..].trim();
+    if body.is_empty() {
+        return None;
+    }
+    let mut parts = body.splitn(2, DIRECTIVE_VALUE_SEPARATOR);
+    let name = parts.next().unwrap().trim().to_ascii_lowercase();
+    let value = parts
+        .next()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty());
+    Some(Directive { name, value })
+}
+
+/// Parses durations like `500ms`, `30s`, `5m` or `1h`, None if it's invalid
+/// or too long to be represented.
+fn parse_duration(val: &str) -> Option<Duration> {
+    let val = val.trim();
+    let unit_pos = val.find(|c: char| !c.is_ascii_digit())?;
+    let amount = val[..unit_pos].parse::<u64>().ok()?;
+    match val[unit_pos..].trim() {
+        "ms" => Some(Duration::from_millis(amount)),
+        "s" => Some(Duration::from_secs(amount)),
+        "m" => amount.checked_mul(60).map(Duration::from_secs),
+        "h" => amount.checked_mul(60 * 60).map(Duration::from_secs),
+        _ => None,
+    }
+}
+
+/// Compares a word with a keyword, CQL keywords are case-insensitive.
+fn is_keyword(word: &str, keyword: &str) -> bool {
+    word.eq_ignore_ascii_case(keyword)
+}
+
+/// Checks that the line starts with the given sequence of keywords.
+fn starts_with_keywords(line: &str, keywords: &[&str]) -> bool {
+    let mut words = line.split_whitespace();
+    keywords.iter().all(|kw| match words.next() {
+        Some(word) => is_keyword(word.trim_end_matches(QUERIES_SEPARATOR), kw),
+        None => false,
+    })
+}
+
+/// Checks that the line ends with the given sequence of keywords,
+/// ignoring the trailing queries separator.
+fn ends_with_keywords(line: &str, keywords: &[&str]) -> bool {
+    let mut words = line
+        .trim_end_matches(QUERIES_SEPARATOR)
+        .split_whitespace()
+        .rev();
+    keywords.iter().rev().all(|kw| match words.next() {
+        Some(word) => is_keyword(word, kw),
+        None => false,
+    })
+}
+
+/// Checks for `BEGIN UNLOGGED BATCH`, `BEGIN LOGGED BATCH` or `BEGIN COUNTER BATCH`.
+fn starts_with_typed_batch(line: &str) -> bool {
+    let words: Vec<&str> = line.split_whitespace().take(3).collect();
+    words.len() == 3
+        && is_keyword(words[0], BATCH_BEGIN[0])
+        && BATCH_BEGIN_TYPED.iter().any(|kw| is_keyword(words[1], kw))
+        && is_keyword(words[2], BATCH_BEGIN[1])
+}
+
+fn is_cql_comment_line(line: &str) -> bool {
+    line.starts_with(COMMENT_LINE_TYPE_1) || line.starts_with(COMMENT_LINE_TYPE_2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(cql: &str) -> Vec<String> {
+        parse_cql(cql.as_bytes(), &ParseOptions::default())
+            .unwrap()
+            .queries
+    }
+
+    fn parse_strict(cql: &str) -> Result<Vec<String>> {
+        let options = ParseOptions {
+            require_terminators: true,
+            ..ParseOptions::default()
+        };
+        parse_cql(cql.as_bytes(), &options).map(|cql| cql.queries)
+    }
+
+    #[test]
+    fn comment_markers_in_quoted_identifiers() {
+        assert_eq!(
+            parse("CREATE TABLE \"a//b\" (id int primary key);\nSELECT 1;"),
+            vec!["CREATE TABLE \"a//b\" (id int primary key);", "SELECT 1;"]
+        );
+        assert_eq!(
+            parse("SELECT \"a--\"\"b\" FROM t; -- comment\nSELECT 1;"),
+            vec!["SELECT \"a--\"\"b\" FROM t;", "SELECT 1;"]
+        );
+    }
+
+    #[test]
+    fn comment_markers_in_function_bodies() {
+        assert_eq!(
+            parse("CREATE FUNCTION f() AS $$ return \"http://x\"; $$;\nSELECT 1;"),
+            vec![
+                "CREATE FUNCTION f() AS $$ return \"http://x\"; $$;",
+                "SELECT 1;"
+            ]
+        );
+        assert_eq!(
+            parse("CREATE FUNCTION f() AS $$ /* x */ return 1; $$;"),
+            vec!["CREATE FUNCTION f() AS $$ /* x */ return 1; $$;"]
+        );
+    }
+
+    #[test]
+    fn apostrophe_in_function_body() {
+        assert_eq!(
+            parse("CREATE FUNCTION f() AS $$ return \"don't\"; $$;\nSELECT 1;\nSELECT 2;"),
+            vec![
+                "CREATE FUNCTION f() AS $$ return \"don't\"; $$;",
+                "SELECT 1;",
+                "SELECT 2;"
+            ]
+        );
+    }
+
+    #[test]
+    fn function_body_spanning_lines() {
+        assert_eq!(
+            parse("CREATE FUNCTION f() AS $$\n  return 1; // one\n$$;\nSELECT 1;"),
+            vec![
+                "CREATE FUNCTION f() AS $$\nreturn 1; // one\n$$;",
+                "SELECT 1;"
+            ]
+        );
+    }
+
+    #[test]
+    fn alter_table_continuing_on_next_line() {
+        assert_eq!(
+            parse_strict("ALTER TABLE ks.t\nDROP c;").unwrap(),
+            vec!["ALTER TABLE ks.t DROP c;"]
+        );
+        assert_eq!(
+            parse_strict("ALTER TABLE t\nALTER a TYPE text;").unwrap(),
+            vec!["ALTER TABLE t ALTER a TYPE text;"]
+        );
+        assert_eq!(
+            parse_strict("ALTER TABLE IF EXISTS t\nDROP c;").unwrap(),
+            vec!["ALTER TABLE IF EXISTS t DROP c;"]
+        );
+    }
+
+    #[test]
+    fn missing_terminator_in_strict_mode() {
+        assert!(parse_strict("ALTER TABLE t DROP c\nDROP TABLE u;").is_err());
+    }
+}